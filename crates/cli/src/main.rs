@@ -0,0 +1,475 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use imagebox_core::{
+    DataManager, EncodeOptions, OutputFormat, compress_character_image, generate_encoded_image,
+    generate_image,
+};
+
+#[derive(Parser)]
+#[command(name = "imagebox", version, about = "ImageBox 命令行工具")]
+struct Cli {
+    /// 资源配置文件路径
+    #[arg(long, global = true, default_value = "data/data.json")]
+    data: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+#[derive(Args)]
+struct EncodeArgs {
+    /// 输出图片格式，WebP 为无损编码
+    #[arg(long, value_enum, default_value_t = Format::Png)]
+    format: Format,
+
+    /// JPEG 质量，取值范围为 1-100
+    #[arg(long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+
+    /// JPEG 透明部分的背景颜色，格式为 `R,G,B`
+    #[arg(long, default_value = "255,255,255", value_parser = parse_rgb)]
+    background: [u8; 3],
+
+    /// 在输出图片中嵌入 sRGB 色彩配置文件，可用 `=png,jpeg` 限定格式，不指定时对所有格式生效
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..,
+        value_delimiter = ',',
+        require_equals = true,
+        value_name = "FORMATS"
+    )]
+    embed_srgb: Option<Vec<Format>>,
+}
+
+impl EncodeArgs {
+    fn options(&self) -> EncodeOptions {
+        EncodeOptions {
+            format: match self.format {
+                Format::Png => OutputFormat::Png,
+                Format::Jpeg => OutputFormat::Jpeg,
+                Format::Webp => OutputFormat::Webp,
+            },
+            jpeg_quality: self.quality,
+            background: self.background,
+            embed_srgb: self
+                .embed_srgb
+                .as_ref()
+                .is_some_and(|formats| formats.is_empty() || formats.contains(&self.format)),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.format {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::Webp => "webp",
+        }
+    }
+}
+
+// 校准时尝试的 max_image_size，单位为 KB
+const CALIBRATION_SIZES: [usize; 10] = [32, 64, 96, 128, 192, 256, 384, 512, 768, 1024];
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+struct CalibrationRow {
+    max_image_size: usize,
+    width: u32,
+    height: u32,
+    scale: f32,
+    encoded_kb: usize,
+    within_budget: bool,
+}
+
+#[derive(Serialize)]
+struct CalibrationReport {
+    character: String,
+    format: &'static str,
+    budget_kb: usize,
+    samples: usize,
+    original_width: u32,
+    original_height: u32,
+    original_kb: usize,
+    rows: Vec<CalibrationRow>,
+    recommended: Option<usize>,
+}
+
+fn parse_rgb(value: &str) -> Result<[u8; 3], String> {
+    let channels = value
+        .split(',')
+        .map(|c| c.trim().parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    channels
+        .try_into()
+        .map_err(|_| "颜色格式应为 `R,G,B`".to_string())
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 生成图片并输出到文件或标准输出
+    Render {
+        /// 角色 ID
+        #[arg(short, long)]
+        character: String,
+
+        /// 文字内容，为 `-` 时从标准输入读取
+        #[arg(short, long)]
+        text: String,
+
+        /// 引用的上文，绘制在角色的 `context_textarea` 中
+        #[arg(long)]
+        context: Option<String>,
+
+        /// 输出路径，为 `-` 时写入标准输出
+        #[arg(short, long, default_value = "-")]
+        out: String,
+
+        /// 图片最大预估大小，单位为 KB，为 0 时不进行压缩
+        #[arg(long, default_value_t = 0)]
+        max_image_size: usize,
+
+        #[command(flatten)]
+        encode: EncodeArgs,
+    },
+    /// 批量生成图片，输入文件中每行文字生成一张图片
+    Batch {
+        /// 角色 ID
+        #[arg(short, long)]
+        character: String,
+
+        /// 输入文件路径，每行为一条文字
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// 输出目录
+        #[arg(short, long)]
+        out_dir: PathBuf,
+
+        /// 并行生成的线程数，默认为可用的 CPU 核心数
+        #[arg(short, long, env = "IMAGEBOX_JOBS")]
+        jobs: Option<NonZeroUsize>,
+
+        /// 图片最大预估大小，单位为 KB，为 0 时不进行压缩
+        #[arg(long, default_value_t = 0)]
+        max_image_size: usize,
+
+        #[command(flatten)]
+        encode: EncodeArgs,
+    },
+    /// 渲染示例文字并报告不同 max_image_size 下的图片尺寸与编码大小
+    Calibrate {
+        /// 角色 ID
+        #[arg(short, long)]
+        character: String,
+
+        /// 示例文字
+        #[arg(short, long, default_value = "这是一段用于校准图片大小的【示例文字】")]
+        text: String,
+
+        /// 目标大小上限，单位为 KB
+        #[arg(long, default_value_t = 1024)]
+        budget: usize,
+
+        /// 渲染次数，每次可能选择不同的背景，结果取最大值
+        #[arg(long, default_value_t = NonZeroUsize::new(5).unwrap())]
+        samples: NonZeroUsize,
+
+        /// 报告格式
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        report: ReportFormat,
+
+        #[command(flatten)]
+        encode: EncodeArgs,
+    },
+}
+
+fn read_text(text: String) -> Result<String> {
+    if text != "-" {
+        return Ok(text);
+    }
+
+    let mut buf = String::new();
+    io::stdin()
+        .read_to_string(&mut buf)
+        .context("读取标准输入失败")?;
+    Ok(buf.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn write_output(out: &str, bytes: &[u8]) -> Result<()> {
+    if out == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(bytes).context("写入标准输出失败")?;
+        stdout.flush()?;
+    } else {
+        fs::write(out, bytes).with_context(|| format!("写入文件 '{}' 失败", out))?;
+    }
+    Ok(())
+}
+
+fn render_batch(
+    data_manager: &DataManager,
+    character: &str,
+    texts: &[&str],
+    out_dir: &Path,
+    jobs: usize,
+    max_image_size: usize,
+    encode: &EncodeArgs,
+) -> Result<()> {
+    let options = encode.options();
+
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        let workers = (0..jobs.min(texts.len()))
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(text) = texts.get(index) else {
+                            return Ok(());
+                        };
+
+                        let bytes = generate_encoded_image(
+                            data_manager,
+                            character,
+                            text,
+                            None,
+                            max_image_size,
+                            None,
+                            &options,
+                        )?;
+                        let out = out_dir.join(format!("{:04}.{}", index + 1, encode.extension()));
+                        fs::write(&out, &bytes)
+                            .with_context(|| format!("写入文件 '{}' 失败", out.display()))?;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().unwrap())
+    })
+}
+
+fn calibrate(
+    data_manager: &DataManager,
+    character: &str,
+    text: &str,
+    budget: usize,
+    samples: usize,
+    options: &EncodeOptions,
+) -> Result<CalibrationReport> {
+    let images = (0..samples)
+        .map(|_| generate_image(data_manager, character, text, None, 0, None))
+        .collect::<Result<Vec<_>>>()?;
+
+    // 各次渲染中编码后最大的结果
+    let measure = |max_size: usize| -> Result<(u32, u32, usize)> {
+        let mut worst = (0, 0, 0);
+        for image in &images {
+            // 与生成时相同，设置了 output_size 的角色不缩小图片
+            let (compressed, encoded) = compress_character_image(
+                data_manager,
+                character,
+                image.clone(),
+                max_size,
+                options,
+            )?;
+            let encoded_kb = encoded.len().div_ceil(1024);
+            if encoded_kb >= worst.2 {
+                worst = (compressed.width(), compressed.height(), encoded_kb);
+            }
+        }
+        Ok(worst)
+    };
+
+    let (original_width, original_height, original_kb) = measure(0)?;
+    let rows = CALIBRATION_SIZES
+        .into_iter()
+        .map(|max_image_size| {
+            let (width, height, encoded_kb) = measure(max_image_size)?;
+            Ok(CalibrationRow {
+                max_image_size,
+                width,
+                height,
+                scale: width as f32 / original_width as f32,
+                encoded_kb,
+                within_budget: encoded_kb <= budget,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // 原图已满足上限时无需压缩，否则选择满足上限的最大设置以保留尽可能多的细节
+    let recommended = if original_kb <= budget {
+        Some(0)
+    } else {
+        rows.iter()
+            .filter(|row| row.within_budget)
+            .map(|row| row.max_image_size)
+            .max()
+    };
+
+    Ok(CalibrationReport {
+        character: character.to_string(),
+        format: match options.format {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Webp => "webp",
+        },
+        budget_kb: budget,
+        samples,
+        original_width,
+        original_height,
+        original_kb,
+        rows,
+        recommended,
+    })
+}
+
+fn print_report(report: &CalibrationReport) {
+    println!(
+        "角色 '{}'，{} 格式，渲染 {} 次，原图最大 {}x{}，{} KB",
+        report.character,
+        report.format,
+        report.samples,
+        report.original_width,
+        report.original_height,
+        report.original_kb
+    );
+    println!();
+    println!("max_image_size    尺寸          缩放    编码大小");
+    for row in &report.rows {
+        println!(
+            "{:>10} KB    {:>5}x{:<5}    {:>4.0}%    {:>5} KB{}",
+            row.max_image_size,
+            row.width,
+            row.height,
+            row.scale * 100.0,
+            row.encoded_kb,
+            if row.within_budget {
+                ""
+            } else {
+                "  超出上限"
+            }
+        );
+    }
+    println!();
+    match report.recommended {
+        Some(0) => println!(
+            "原图已不超过 {} KB，推荐 max_image_size = 0",
+            report.budget_kb
+        ),
+        Some(size) => println!("推荐 max_image_size = {}", size),
+        None => println!("没有设置能满足 {} KB 的上限", report.budget_kb),
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let data_manager = DataManager::new(&cli.data)?;
+
+    match cli.command {
+        Command::Render {
+            character,
+            text,
+            context,
+            out,
+            max_image_size,
+            encode,
+        } => {
+            let text = read_text(text)?;
+            let report = data_manager.would_truncate(&character, &text)?;
+            if report.truncated {
+                eprintln!(
+                    "警告：文字在最小字号 {} 下仍超出文字区域，超出部分将被截断",
+                    report.font_size
+                );
+            }
+            let bytes = generate_encoded_image(
+                &data_manager,
+                &character,
+                &text,
+                context.as_deref(),
+                max_image_size,
+                None,
+                &encode.options(),
+            )?;
+            write_output(&out, &bytes)?;
+        }
+        Command::Batch {
+            character,
+            input,
+            out_dir,
+            jobs,
+            max_image_size,
+            encode,
+        } => {
+            let content = fs::read_to_string(&input).context("读取输入文件失败")?;
+            let texts = content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect::<Vec<_>>();
+            let jobs = jobs
+                .or_else(|| thread::available_parallelism().ok())
+                .map_or(1, NonZeroUsize::get);
+
+            fs::create_dir_all(&out_dir).context("创建输出目录失败")?;
+            render_batch(
+                &data_manager,
+                &character,
+                &texts,
+                &out_dir,
+                jobs,
+                max_image_size,
+                &encode,
+            )?;
+        }
+        Command::Calibrate {
+            character,
+            text,
+            budget,
+            samples,
+            report: report_format,
+            encode,
+        } => {
+            let report = calibrate(
+                &data_manager,
+                &character,
+                &text,
+                budget,
+                samples.get(),
+                &encode.options(),
+            )?;
+            match report_format {
+                ReportFormat::Text => print_report(&report),
+                ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            }
+        }
+    }
+
+    Ok(())
+}
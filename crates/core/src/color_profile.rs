@@ -0,0 +1,114 @@
+use std::sync::OnceLock;
+
+// D50 参考白点
+const D50: [f64; 3] = [0.964203, 1.0, 0.824905];
+// 经 Bradford 变换适配到 D50 的 sRGB 三原色
+const SRGB_RED: [f64; 3] = [0.436066, 0.222488, 0.013916];
+const SRGB_GREEN: [f64; 3] = [0.385147, 0.716873, 0.097076];
+const SRGB_BLUE: [f64; 3] = [0.143066, 0.060608, 0.714096];
+// D65 到 D50 的 Bradford 色适应矩阵
+const CHROMATIC_ADAPTATION: [f64; 9] = [
+    1.047882, 0.022919, -0.050217, 0.029587, 0.990479, -0.017059, -0.009232, 0.015076, 0.751678,
+];
+// sRGB 传递函数的参数曲线(类型 3)：g, a, b, c, d
+const SRGB_TRC: [f64; 5] = [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045];
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn fixed_array(values: &[f64]) -> Vec<u8> {
+    values.iter().flat_map(|&v| s15_fixed16(v)).collect()
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    [b"XYZ \0\0\0\0".as_slice(), &fixed_array(&xyz)].concat()
+}
+
+fn matrix_tag(matrix: [f64; 9]) -> Vec<u8> {
+    [b"sf32\0\0\0\0".as_slice(), &fixed_array(&matrix)].concat()
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    let mut tag = b"mluc\0\0\0\0".to_vec();
+    tag.extend(1u32.to_be_bytes());
+    tag.extend(12u32.to_be_bytes());
+    tag.extend(b"enUS");
+    tag.extend((utf16.len() as u32).to_be_bytes());
+    tag.extend(28u32.to_be_bytes());
+    tag.extend(utf16);
+    tag
+}
+
+fn curve_tag() -> Vec<u8> {
+    let mut tag = b"para\0\0\0\0".to_vec();
+    tag.extend(3u16.to_be_bytes());
+    tag.extend([0, 0]);
+    tag.extend(fixed_array(&SRGB_TRC));
+    tag
+}
+
+fn build_srgb_profile() -> Vec<u8> {
+    let curve = curve_tag();
+    let tags: [(&[u8; 4], Vec<u8>); 10] = [
+        (b"desc", text_tag("sRGB")),
+        (b"cprt", text_tag("No copyright")),
+        (b"wtpt", xyz_tag(D50)),
+        (b"chad", matrix_tag(CHROMATIC_ADAPTATION)),
+        (b"rXYZ", xyz_tag(SRGB_RED)),
+        (b"gXYZ", xyz_tag(SRGB_GREEN)),
+        (b"bXYZ", xyz_tag(SRGB_BLUE)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    // 标签数据紧跟在 128 字节的文件头与标签表之后，各自按 4 字节对齐，三条曲线共用同一份数据
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data: Vec<u8> = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    let mut curve_offset = None;
+    for (signature, tag) in &tags {
+        let is_curve = signature.ends_with(b"TRC");
+        let offset = match curve_offset {
+            Some(offset) if is_curve => offset,
+            _ => {
+                let offset = data_start + data.len();
+                data.extend(tag);
+                data.resize(data.len().next_multiple_of(4), 0);
+                if is_curve {
+                    curve_offset = Some(offset);
+                }
+                offset
+            }
+        };
+        table.extend(*signature);
+        table.extend((offset as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+    }
+
+    let size = data_start + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend((size as u32).to_be_bytes());
+    profile.extend([0; 4]); // CMM
+    profile.extend([4, 0x30, 0, 0]); // 版本 4.3
+    profile.extend(b"mntrRGB XYZ ");
+    profile.extend([0x07, 0xea, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]); // 创建日期：2026-01-01
+    profile.extend(b"acsp");
+    profile.extend([0; 24]); // 平台、标志、设备制造商、型号与属性
+    profile.extend([0; 4]); // 渲染意图：感知
+    profile.extend(fixed_array(&D50));
+    profile.extend([0; 4]); // 创建者
+    profile.extend([0; 16]); // 配置文件 ID
+    profile.extend([0; 28]);
+    profile.extend(table);
+    profile.extend(data);
+    profile
+}
+
+/// 精简的 sRGB ICC 配置文件(v4)，用于嵌入输出图片
+pub fn srgb_icc_profile() -> &'static [u8] {
+    static PROFILE: OnceLock<Vec<u8>> = OnceLock::new();
+    PROFILE.get_or_init(build_srgb_profile)
+}
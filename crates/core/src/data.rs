@@ -1,7 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
 
 use image::Rgba;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::named_colors::named_color;
 
 #[derive(Deserialize, Serialize, Clone)]
 #[serde(untagged)]
@@ -9,44 +13,326 @@ pub enum ColorInput {
     RgbaArr([u8; 4]),
     RgbArr([u8; 3]),
     Literal(String),
+    /// 色相范围为 0 到 360，饱和度与亮度范围为 0 到 1
+    Hsl {
+        h: f32,
+        s: f32,
+        l: f32,
+        #[serde(default = "default_alpha")]
+        a: u8,
+    },
+    /// 色相范围为 0 到 360，饱和度与明度范围为 0 到 1
+    Hsv {
+        h: f32,
+        s: f32,
+        v: f32,
+        #[serde(default = "default_alpha")]
+        a: u8,
+    },
+}
+
+fn default_alpha() -> u8 {
+    255
+}
+
+/// 像素值，或形如 `"50%"` 的相对于背景图片宽度(横向)或高度(纵向)的百分比，用作坐标时负数从右边缘或下边缘起算
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Length {
+    Pixels(i32),
+    Relative(String),
+}
+
+impl Length {
+    fn percent(&self) -> Option<f32> {
+        match self {
+            Length::Pixels(_) => None,
+            Length::Relative(s) => s.strip_suffix('%')?.trim().parse().ok(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        matches!(self, Length::Pixels(_)) || self.percent().is_some()
+    }
+
+    /// 按参照长度换算为像素值，无效的百分比按 0 处理
+    pub fn resolve(&self, total: u32) -> i32 {
+        match self {
+            Length::Pixels(value) => *value,
+            Length::Relative(_) => self
+                .percent()
+                .map_or(0, |percent| (total as f32 * percent / 100.0).round() as i32),
+        }
+    }
+
+    /// 按参照长度换算为坐标，负数表示到右边缘或下边缘的距离
+    pub fn resolve_position(&self, total: u32) -> i32 {
+        match self.resolve(total) {
+            value if value < 0 => total as i32 + value,
+            value => value,
+        }
+    }
 }
 
 pub const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
 pub const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
 
 impl ColorInput {
+    /// 是否为按背景亮度在深浅两色之间选择的 `"auto-contrast"`
+    pub fn is_auto_contrast(&self) -> bool {
+        matches!(self, ColorInput::Literal(s) if s == "auto-contrast")
+    }
+
     pub fn to_rgba(&self, primary: Rgba<u8>) -> Rgba<u8> {
         match self {
             ColorInput::RgbaArr(c) => Rgba(*c),
             ColorInput::RgbArr([r, g, b]) => Rgba([*r, *g, *b, 255]),
             ColorInput::Literal(s) => match s.as_str() {
                 "primary" => primary,
-                "white" => WHITE,
-                _ => BLACK,
+                _ => named_color(s)
+                    .or_else(|| parse_hex_color(s))
+                    .unwrap_or_else(|| {
+                        warn_unknown_color(s);
+                        BLACK
+                    }),
             },
+            ColorInput::Hsl { h, s, l, a } => {
+                let (s, l) = (s.clamp(0.0, 1.0), l.clamp(0.0, 1.0));
+                let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+                hue_to_rgba(*h, chroma, l - chroma / 2.0, *a)
+            }
+            ColorInput::Hsv { h, s, v, a } => {
+                let (s, v) = (s.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+                let chroma = v * s;
+                hue_to_rgba(*h, chroma, v - chroma, *a)
+            }
         }
     }
 }
 
+/// 由色相、色度与明暗偏移量计算颜色，HSL 与 HSV 共用
+fn hue_to_rgba(hue: f32, chroma: f32, offset: f32, alpha: u8) -> Rgba<u8> {
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let [r, g, b] = [r, g, b].map(|value| ((value + offset) * 255.0).round() as u8);
+    Rgba([r, g, b, alpha])
+}
+
+/// 解析 `#rgb`、`#rrggbb` 与 `#rrggbbaa` 格式的十六进制颜色，不区分大小写
+fn parse_hex_color(s: &str) -> Option<Rgba<u8>> {
+    let hex = s.strip_prefix('#')?;
+    if !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize, len: usize| u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).ok();
+    match hex.len() {
+        3 => {
+            let [r, g, b] = [0, 1, 2].map(|i| channel(i, 1).map(|v| v * 17));
+            Some(Rgba([r?, g?, b?, 255]))
+        }
+        6 => {
+            let [r, g, b] = [0, 1, 2].map(|i| channel(i, 2));
+            Some(Rgba([r?, g?, b?, 255]))
+        }
+        8 => {
+            let [r, g, b, a] = [0, 1, 2, 3].map(|i| channel(i, 2));
+            Some(Rgba([r?, g?, b?, a?]))
+        }
+        _ => None,
+    }
+}
+
+/// 每个未知的颜色字符串只警告一次，避免每次生成图片时重复输出
+fn warn_unknown_color(s: &str) {
+    static WARNED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    let mut warned = WARNED.get_or_init(Default::default).lock().unwrap();
+    if warned.insert(s.to_string()) {
+        eprintln!("警告：未知的颜色 '{}'，使用黑色代替", s);
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "lowercase")]
 pub enum ObjectConfig {
     Text {
         text: String,
-        position: [i32; 2],
+        position: [Length; 2],
         font_color: ColorInput,
         font_size: u32,
+        /// `position` 对应的物件上的点
+        #[serde(default)]
+        anchor: Anchor,
+        /// 每次生成时在 `position` 基础上随机偏移的最大距离
+        #[serde(skip_serializing_if = "Option::is_none")]
+        jitter: Option<[i32; 2]>,
+        /// 绘制顺序，与其他物件及文字区域一同按从小到大的顺序绘制
+        #[serde(default)]
+        z: i32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<ObjectCondition>,
     },
     Image {
-        position: [i32; 2],
+        position: [Length; 2],
+        /// `position` 对应的物件上的点
+        #[serde(default)]
+        anchor: Anchor,
+        /// 每次生成时在 `position` 基础上随机偏移的最大距离
+        #[serde(skip_serializing_if = "Option::is_none")]
+        jitter: Option<[i32; 2]>,
         #[serde(skip_serializing_if = "Option::is_none")]
         path: Option<Vec<String>>,
         #[serde(skip_serializing_if = "Option::is_none")]
+        groups: Option<Vec<ImageGroup>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         id: Option<String>,
+        /// 不透明度，范围为 0 到 1，与图片自身的透明度相乘
+        #[serde(skip_serializing_if = "Option::is_none")]
+        opacity: Option<f32>,
+        /// 绘制前的缩放倍数
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scale: Option<f32>,
+        /// 绘制前缩放到的大小，同时指定时优先于 `scale`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        size: Option<[u32; 2]>,
+        /// 绕图片中心顺时针旋转的角度(度)，`anchor` 按旋转后的包围盒计算
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rotation: Option<f32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        blend: Option<BlendMode>,
+        /// 绘制顺序，与其他物件及文字区域一同按从小到大的顺序绘制
+        #[serde(default)]
+        z: i32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<ObjectCondition>,
     },
 }
 
+impl ObjectConfig {
+    pub fn z(&self) -> i32 {
+        match self {
+            ObjectConfig::Text { z, .. } | ObjectConfig::Image { z, .. } => *z,
+        }
+    }
+
+    pub fn position(&self) -> &[Length; 2] {
+        match self {
+            ObjectConfig::Text { position, .. } | ObjectConfig::Image { position, .. } => position,
+        }
+    }
+
+    pub fn jitter(&self) -> Option<[i32; 2]> {
+        match self {
+            ObjectConfig::Text { jitter, .. } | ObjectConfig::Image { jitter, .. } => *jitter,
+        }
+    }
+
+    /// 未设置条件时总是绘制
+    pub fn is_shown(&self, text: &str) -> bool {
+        match self {
+            ObjectConfig::Text { when, .. } | ObjectConfig::Image { when, .. } => {
+                when.as_ref().is_none_or(|when| when.matches(text))
+            }
+        }
+    }
+}
+
+/// 物件的绘制条件，按输入文字的字符数判断，上下限均包含在内
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ObjectCondition {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_chars: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_chars: Option<usize>,
+}
+
+impl ObjectCondition {
+    pub fn matches(&self, text: &str) -> bool {
+        let chars = text.chars().count();
+        self.min_chars.is_none_or(|min| chars >= min)
+            && self.max_chars.is_none_or(|max| chars <= max)
+    }
+}
+
+/// 物件上与 `position` 对齐的点
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// 将锚点位置换算为指定大小的物件左上角位置
+    pub fn to_top_left(self, [x, y]: [i32; 2], width: u32, height: u32) -> [i32; 2] {
+        let (width, height) = (width as i32, height as i32);
+        let dx = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => width / 2,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => width,
+        };
+        let dy = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0,
+            Anchor::Left | Anchor::Center | Anchor::Right => height / 2,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => height,
+        };
+        [x - dx, y - dy]
+    }
+}
+
+/// 图片物件与下方图像的混合模式
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+}
+
+impl BlendMode {
+    /// 按混合模式合成单个颜色通道，取值范围均为 0 到 1
+    pub fn apply(self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Screen => backdrop + source - backdrop * source,
+            BlendMode::Overlay if backdrop <= 0.5 => 2.0 * backdrop * source,
+            BlendMode::Overlay => 1.0 - 2.0 * (1.0 - backdrop) * (1.0 - source),
+            BlendMode::Add => (backdrop + source).min(1.0),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ImageGroup {
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    pub path: Vec<String>,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
 #[derive(Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum HorizontalAlign {
@@ -54,6 +340,8 @@ pub enum HorizontalAlign {
     Left,
     Center,
     Right,
+    /// 两端对齐，各段末行按起始方向对齐，用于文本块整体对齐时等同于 `Left`
+    Justify,
 }
 
 #[derive(Deserialize, Serialize, Clone, Default)]
@@ -65,23 +353,241 @@ pub enum VerticalAlign {
     Bottom,
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextDirection {
+    #[default]
+    Ltr,
+    Rtl,
+    /// 按每段第一个强方向字符判断
+    Auto,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WritingMode {
+    #[default]
+    Horizontal,
+    /// 竖排，各列从右到左排列
+    VerticalRl,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TextOverflow {
+    /// 缩小字号直至放下，最小字号下仍放不下的部分不绘制
+    #[default]
+    Shrink,
+    /// 最小字号下仍放不下时截断并以省略号结尾
+    Ellipsis,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum BackgroundSelection {
+    #[default]
+    Random,
+    AspectRatio {
+        short_text: usize,
+        long_text: usize,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFit {
+    Stretch,
+    #[default]
+    Contain,
+    Cover,
+}
+
+/// 高亮颜色，单个颜色用于所有级别，列表按级别依次对应
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum HighlightInput {
+    Single(ColorInput),
+    Levels(Vec<ColorInput>),
+}
+
+impl HighlightInput {
+    pub fn get(&self, level: usize) -> Option<&ColorInput> {
+        match self {
+            HighlightInput::Single(color) => Some(color),
+            HighlightInput::Levels(colors) => colors.get(level),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TextStroke {
+    pub color: ColorInput,
+    pub width: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct BoxFill {
+    pub color: ColorInput,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub corner_radius: u32,
+    #[serde(default)]
+    pub padding: u32,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_tab_width() -> u32 {
+    4
+}
+
+fn default_supersample() -> u32 {
+    1
+}
+
+impl TextAreaConfig {
+    /// 将百分比形式及相对右下边缘的位置与大小按画布尺寸换算为像素值
+    pub fn resolve(&self, [width, height]: [u32; 2]) -> TextAreaConfig {
+        let [x, y] = &self.position;
+        let [size_x, size_y] = &self.size;
+        TextAreaConfig {
+            position: [x.resolve_position(width), y.resolve_position(height)].map(Length::Pixels),
+            size: [size_x.resolve(width), size_y.resolve(height)].map(Length::Pixels),
+            ..self.clone()
+        }
+    }
+
+    /// 区域左上角的像素坐标，百分比需先通过 `resolve` 换算
+    pub fn pixel_position(&self) -> [i32; 2] {
+        [self.position[0].resolve(0), self.position[1].resolve(0)]
+    }
+
+    /// 区域的像素大小，百分比需先通过 `resolve` 换算
+    pub fn pixel_size(&self) -> [u32; 2] {
+        [self.size[0].resolve(0), self.size[1].resolve(0)].map(|side| side.max(0) as u32)
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct TextAreaConfig {
-    pub position: [i32; 2],
-    pub size: [u32; 2],
+    pub position: [Length; 2],
+    pub size: [Length; 2],
+    /// 绕区域中心顺时针旋转的角度(度)，排版与对齐在旋转前的区域内进行
+    #[serde(default)]
+    pub rotation: f32,
+    /// 绘制顺序，与物件一同按从小到大的顺序绘制，相同时文字区域在物件之后
+    #[serde(default)]
+    pub z: i32,
+    /// 超采样倍数，大于 1 时以放大后的尺寸绘制文字再缩小，为 1 时直接绘制
+    #[serde(default = "default_supersample")]
+    pub supersample: u32,
     pub font_color: ColorInput,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub highlight: Option<ColorInput>,
+    pub highlight: Option<HighlightInput>,
+    /// 高亮的起止标记，设置后替代默认的“【】”与“[]”，仅有一个高亮级别
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight_delimiters: Option<(String, String)>,
+    /// 字号上限，未设置时以区域高度为上限
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_font_size: Option<u32>,
+    /// 字号下限，默认为 1，大于字号上限时以上限为准；
+    /// 缩小到该字号仍放不下时按 `overflow` 处理
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_font_size: Option<u32>,
+    #[serde(default)]
+    pub overflow: TextOverflow,
+    /// 行数上限(竖排时为列数)，最小字号下仍超出时总是以省略号截断
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
     #[serde(default)]
     pub shadow_offset: (i32, i32),
+    /// 阴影的模糊半径(像素)，为 0 时绘制无模糊的阴影
+    #[serde(default)]
+    pub shadow_blur: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke: Option<TextStroke>,
     #[serde(default)]
     pub line_spacing: f32,
     #[serde(default)]
+    pub letter_spacing: f32,
+    #[serde(default = "default_tab_width")]
+    pub tab_width: u32,
+    #[serde(default)]
+    pub paragraph_spacing: f32,
+    /// 段落首行的缩进(像素)，为负时改为缩进段落中除首行外的其余各行
+    #[serde(default)]
+    pub first_line_indent: i32,
+    #[serde(default)]
     pub align: HorizontalAlign,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_align: Option<HorizontalAlign>,
     #[serde(default)]
     pub valign: VerticalAlign,
+    #[serde(default)]
+    pub direction: TextDirection,
+    #[serde(default)]
+    pub writing_mode: WritingMode,
+    #[serde(default)]
+    pub word_wrap: bool,
+    #[serde(default)]
+    pub auto_contrast: bool,
+    /// `font_color` 为 `"auto-contrast"` 时使用的深色与浅色，缺省时为黑色与白色
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contrast_colors: Option<[ColorInput; 2]>,
+    /// 背景平均相对亮度高于此值时使用深色，否则使用浅色
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contrast_threshold: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub box_fill: Option<BoxFill>,
+}
+
+/// 渐变背景的方向
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientDirection {
+    /// 从上到下
+    #[default]
+    Vertical,
+    /// 从左到右
+    Horizontal,
+    /// 从左上角到右下角
+    Diagonal,
+}
+
+/// 纯色或渐变背景，用作后备背景或直接生成的背景，设置 `end_color` 时为从 `color` 到 `end_color` 的渐变
+#[derive(Deserialize, Serialize, Clone)]
+pub struct FallbackBackground {
+    pub size: [u32; 2],
+    pub color: ColorInput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_color: Option<ColorInput>,
+    #[serde(default)]
+    pub direction: GradientDirection,
+}
+
+/// 输出图片的边框，设置 `end_color` 时为从上到下的渐变
+#[derive(Deserialize, Serialize, Clone)]
+pub struct BorderConfig {
+    pub width: u32,
+    pub color: ColorInput,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_color: Option<ColorInput>,
+    /// 是否绘制在图片范围内，否则向外扩展画布
+    #[serde(default)]
+    pub inset: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HeaderConfig {
+    pub avatar: String,
+    pub height: u32,
+    pub background_color: ColorInput,
+    pub name_color: ColorInput,
+    #[serde(default)]
+    pub overlay: bool,
 }
 
 #[derive(Clone)]
@@ -89,43 +595,138 @@ pub struct CharacterConfig {
     pub id: String,
     pub name: String,
     pub backgrounds: Vec<String>,
+    pub background_selection: BackgroundSelection,
     pub font: String,
+    pub emoji_font: Option<String>,
     pub primary_color: Rgba<u8>,
+    /// 主题颜色为 `"auto"` 时按每次所选背景的主色调确定
+    pub auto_primary_color: bool,
     pub objects: Vec<ObjectConfig>,
     pub textarea: TextAreaConfig,
+    pub context_textarea: Option<TextAreaConfig>,
+    pub anchor_to_content: bool,
+    pub output_size: Option<[u32; 2]>,
+    pub output_fit: OutputFit,
+    /// 输出图片的圆角半径，为 0 时不处理
+    pub corner_radius: u32,
+    pub cooldown_ms: Option<u64>,
+    pub caption: Option<String>,
+    pub header: Option<HeaderConfig>,
+    pub border: Option<BorderConfig>,
+    pub fallback_background: Option<FallbackBackground>,
+    /// 直接生成的背景，设置时不再加载背景图片
+    pub generated_background: Option<FallbackBackground>,
+    /// 切换到该角色并生成图片的快捷键，由托盘程序解析
+    pub hotkey: Option<String>,
 }
 
-#[derive(Deserialize, Serialize)]
+/// 模板与样式预设中的文字区域配置，保留原始字段以便按字段合并，使用时再解析为完整配置
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(transparent)]
+pub struct TextAreaFields(Map<String, Value>);
+
+impl TextAreaFields {
+    /// 以 `top` 中设置的字段覆盖同名字段，其余字段保留
+    pub fn merged(&self, top: &TextAreaFields) -> TextAreaFields {
+        let mut fields = self.0.clone();
+        fields.extend(top.0.clone());
+        TextAreaFields(fields)
+    }
+
+    pub fn resolve(&self) -> serde_json::Result<TextAreaConfig> {
+        serde_json::from_value(Value::Object(self.0.clone()))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct Template {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub backgrounds: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_selection: Option<BackgroundSelection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub font: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji_font: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub primary_color: Option<ColorInput>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub objects: Option<Vec<ObjectConfig>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub textarea: Option<TextAreaConfig>,
+    pub textarea: Option<TextAreaFields>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_textarea: Option<TextAreaFields>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anchor_to_content: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_size: Option<[u32; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_fit: Option<OutputFit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corner_radius: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<HeaderConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<BorderConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_background: Option<FallbackBackground>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct CharacterConfigRaw {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub backgrounds: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_selection: Option<BackgroundSelection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub font: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji_font: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub primary_color: Option<ColorInput>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub objects: Option<Vec<ObjectConfig>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub textarea: Option<TextAreaConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_textarea: Option<TextAreaConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anchor_to_content: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_size: Option<[u32; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_fit: Option<OutputFit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corner_radius: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<HeaderConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<BorderConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_background: Option<FallbackBackground>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generated_background: Option<FallbackBackground>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hotkey: Option<String>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default)]
 pub struct DataConfig {
-    pub template: Template,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<Template>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: HashMap<String, Template>,
+    #[serde(default)]
     pub characters: HashMap<String, CharacterConfigRaw>,
 }
@@ -1,30 +1,56 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ab_glyph::FontVec;
+use image::RgbaImage;
+use rand::Rng;
 
 use anyhow::{Context, Result, anyhow, bail};
+use serde::de::DeserializeOwned;
 
-use crate::data::{BLACK, CharacterConfig, DataConfig};
+use crate::data::{BLACK, CharacterConfig, ColorInput, DataConfig, Template, TextAreaFields};
+use crate::image_generator::content_bounds;
+use crate::resource_loader::{FontCache, GlobCache, ImageCache, fallback_font, load_random_image};
+use crate::textarea::{FitReport, TextFonts, check_fit};
 
 pub struct DataManager {
     data_dir: PathBuf,
     character_configs: Vec<CharacterConfig>,
+    font_cache: FontCache,
+    image_cache: ImageCache,
+    glob_cache: GlobCache,
 }
 
 impl DataManager {
     pub fn new(config_path: &Path) -> Result<Self> {
-        if !config_path.exists() || !config_path.is_file() {
+        let data_dir = config_path.parent().unwrap().to_path_buf();
+        let characters_dir = data_dir.join("characters");
+
+        if !config_path.is_file() && !characters_dir.is_dir() {
             bail!("资源配置文件不存在");
         }
 
-        let content = fs::read_to_string(config_path).context("读取资源配置文件失败")?;
-        let character_configs = load_data(&content)?;
+        let mut config = if config_path.is_file() {
+            let content = fs::read_to_string(config_path).context("读取资源配置文件失败")?;
+            serde_json::from_str::<DataConfig>(&content).context("解析资源配置失败")?
+        } else {
+            DataConfig::default()
+        };
+        merge_split_files(&data_dir, &mut config)?;
+
+        let character_configs = load_data(config)?;
         if character_configs.is_empty() {
             bail!("资源配置中没有角色");
         }
 
         Ok(DataManager {
-            data_dir: config_path.parent().unwrap().to_path_buf(),
+            data_dir,
             character_configs,
+            font_cache: FontCache::default(),
+            image_cache: ImageCache::default(),
+            glob_cache: GlobCache::default(),
         })
     }
 
@@ -36,6 +62,51 @@ impl DataManager {
         &self.character_configs
     }
 
+    pub fn would_truncate(&self, character_id: &str, text: &str) -> Result<FitReport> {
+        let character_config = self
+            .get_character(character_id)
+            .ok_or_else(|| anyhow!("角色 '{}' 不存在", character_id))?;
+        let font = self
+            .load_font(character_config)
+            .ok_or_else(|| anyhow!("无法加载角色 '{}' 的字体文件", character_id))?;
+        let emoji_font = self.load_emoji_font(character_config);
+
+        Ok(check_fit(
+            text,
+            TextFonts::new(&font, emoji_font.as_deref()),
+            &character_config
+                .textarea
+                .resolve(self.canvas_size(character_config)),
+        ))
+    }
+
+    /// 换算百分比坐标使用的画布尺寸，设置了生成背景时使用其尺寸，
+    /// 否则取第一张可读取的背景图片(以可见内容为坐标系时取其可见内容的尺寸)，
+    /// 均无法读取时使用后备背景的尺寸
+    fn canvas_size(&self, character_config: &CharacterConfig) -> [u32; 2] {
+        if let Some(generated) = &character_config.generated_background {
+            return generated.size;
+        }
+        self.get_backgrounds(character_config)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|path| {
+                if character_config.anchor_to_content {
+                    let image = self.image_cache.get(path)?;
+                    Some(content_bounds(&image).map_or(image.dimensions().into(), |(_, size)| size))
+                } else {
+                    image::image_dimensions(path).ok().map(<[u32; 2]>::from)
+                }
+            })
+            .or_else(|| {
+                character_config
+                    .fallback_background
+                    .as_ref()
+                    .map(|fallback| fallback.size)
+            })
+            .unwrap_or_default()
+    }
+
     pub(crate) fn get_backgrounds(
         &self,
         character_config: &CharacterConfig,
@@ -44,7 +115,7 @@ impl DataManager {
         let mut backgrounds = Vec::new();
 
         for pattern in &character_config.backgrounds {
-            let paths = collect_image_paths(&background_dir, pattern);
+            let paths = self.glob_cache.get(&background_dir, pattern);
             backgrounds.extend(paths);
         }
 
@@ -67,7 +138,7 @@ impl DataManager {
 
         for pattern in paths {
             let resolved_pattern = pattern.replace("%c", &character_config.id);
-            let image_paths = collect_image_paths(&images_dir, &resolved_pattern);
+            let image_paths = self.glob_cache.get(&images_dir, &resolved_pattern);
             result.extend(image_paths);
         }
 
@@ -76,38 +147,165 @@ impl DataManager {
         result
     }
 
-    pub(crate) fn get_font_path(&self, character_config: &CharacterConfig) -> PathBuf {
-        self.data_dir.join("fonts").join(&character_config.font)
+    /// 从路径中随机加载一张图片，解码结果会被缓存并在多次生成间共享
+    pub(crate) fn load_random_image<R: Rng>(
+        &self,
+        rng: &mut R,
+        paths: &[PathBuf],
+    ) -> Option<Arc<RgbaImage>> {
+        load_random_image(rng, paths, &self.image_cache)
+    }
+
+    pub(crate) fn load_font(&self, character_config: &CharacterConfig) -> Option<Arc<FontVec>> {
+        let font_path = self.data_dir.join("fonts").join(&character_config.font);
+        self.font_cache.get(&font_path).or_else(|| {
+            let font = fallback_font()?;
+            eprintln!(
+                "字体文件 '{}' 加载失败，使用内置后备字体",
+                font_path.display()
+            );
+            Some(font)
+        })
+    }
+
+    /// 加载角色配置的 emoji 字体，未配置或加载失败时返回 `None`，emoji 改用主字体绘制
+    pub(crate) fn load_emoji_font(
+        &self,
+        character_config: &CharacterConfig,
+    ) -> Option<Arc<FontVec>> {
+        let font_path = self
+            .data_dir
+            .join("fonts")
+            .join(character_config.emoji_font.as_ref()?);
+        let font = self.font_cache.get(&font_path);
+        if font.is_none() {
+            eprintln!(
+                "emoji 字体文件 '{}' 加载失败，使用主字体",
+                font_path.display()
+            );
+        }
+        font
     }
 }
 
-fn collect_image_paths(dir: &Path, pattern: &str) -> Vec<PathBuf> {
-    let mut path_list = Vec::new();
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("读取资源配置文件 '{}' 失败", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("解析资源配置文件 '{}' 失败", path.display()))
+}
 
-    if !dir.exists() || !dir.is_dir() {
-        return path_list;
+/// 合并 `template.json` 与 `characters/*.json` 中的分文件配置，角色 ID 为文件名
+fn merge_split_files(data_dir: &Path, config: &mut DataConfig) -> Result<()> {
+    let template_path = data_dir.join("template.json");
+    if template_path.is_file() {
+        if config.template.is_some() {
+            bail!("模板不能同时在 data.json 与 template.json 中定义");
+        }
+        config.template = Some(read_json(&template_path)?);
     }
 
-    let full_pattern = dir.join(pattern).to_string_lossy().to_string();
+    let characters_dir = data_dir.join("characters");
+    if !characters_dir.is_dir() {
+        return Ok(());
+    }
 
-    if let Ok(paths) = glob::glob(&full_pattern) {
-        for entry in paths.flatten() {
-            if entry.is_file() {
-                path_list.push(entry);
-            }
+    let mut paths = fs::read_dir(&characters_dir)
+        .context("读取角色配置目录失败")?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    paths.sort_unstable();
+
+    for path in paths {
+        let Some(id) = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+        else {
+            continue;
+        };
+        if config.characters.contains_key(&id) {
+            bail!("角色 '{}' 重复定义", id);
         }
+        config.characters.insert(id, read_json(&path)?);
     }
 
-    path_list
+    Ok(())
 }
 
-fn load_data(content: &str) -> Result<Vec<CharacterConfig>> {
-    let config = serde_json::from_str::<DataConfig>(content).context("解析资源配置失败")?;
+fn concat_lists<T: Clone>(base: &Option<Vec<T>>, top: &Option<Vec<T>>) -> Option<Vec<T>> {
+    match (base, top) {
+        (Some(base), Some(top)) => Some([base.as_slice(), top.as_slice()].concat()),
+        (base, top) => top.clone().or_else(|| base.clone()),
+    }
+}
+
+/// 文字区域按字段合并，`top` 中设置的字段优先
+fn merge_textarea(
+    base: &Option<TextAreaFields>,
+    top: &Option<TextAreaFields>,
+) -> Option<TextAreaFields> {
+    match (base, top) {
+        (Some(base), Some(top)) => Some(base.merged(top)),
+        (base, top) => top.clone().or_else(|| base.clone()),
+    }
+}
 
-    let template = config.template;
+/// 将模板叠加在样式预设之上，模板中的值优先，文字区域按字段合并，
+/// 背景与物件按样式、模板的顺序合并
+fn apply_style(template: &Template, style: &Template) -> Template {
+    Template {
+        backgrounds: concat_lists(&style.backgrounds, &template.backgrounds),
+        background_selection: template
+            .background_selection
+            .clone()
+            .or_else(|| style.background_selection.clone()),
+        font: template.font.clone().or_else(|| style.font.clone()),
+        emoji_font: template
+            .emoji_font
+            .clone()
+            .or_else(|| style.emoji_font.clone()),
+        primary_color: template
+            .primary_color
+            .clone()
+            .or_else(|| style.primary_color.clone()),
+        objects: concat_lists(&style.objects, &template.objects),
+        textarea: merge_textarea(&style.textarea, &template.textarea),
+        context_textarea: merge_textarea(&style.context_textarea, &template.context_textarea),
+        anchor_to_content: template.anchor_to_content.or(style.anchor_to_content),
+        output_size: template.output_size.or(style.output_size),
+        output_fit: template.output_fit.or(style.output_fit),
+        corner_radius: template.corner_radius.or(style.corner_radius),
+        cooldown_ms: template.cooldown_ms.or(style.cooldown_ms),
+        caption: template.caption.clone().or_else(|| style.caption.clone()),
+        header: template.header.clone().or_else(|| style.header.clone()),
+        border: template.border.clone().or_else(|| style.border.clone()),
+        fallback_background: template
+            .fallback_background
+            .clone()
+            .or_else(|| style.fallback_background.clone()),
+    }
+}
+
+fn load_data(config: DataConfig) -> Result<Vec<CharacterConfig>> {
+    let template = config.template.unwrap_or_default();
+    let styled_templates = config
+        .styles
+        .iter()
+        .map(|(name, style)| (name.as_str(), apply_style(&template, style)))
+        .collect::<HashMap<_, _>>();
     let mut result = Vec::new();
 
     for (id, raw_character) in config.characters {
+        // 引用样式预设时以样式与模板合并后的结果作为模板
+        let template = match &raw_character.style {
+            Some(style) => styled_templates
+                .get(style.as_str())
+                .ok_or_else(|| anyhow!("角色 '{}' 引用的样式 '{}' 不存在", id, style))?,
+            None => &template,
+        };
+
         let mut backgrounds = Vec::new();
         if let Some(template_bg) = &template.backgrounds {
             backgrounds.extend(template_bg.clone());
@@ -116,13 +314,25 @@ fn load_data(content: &str) -> Result<Vec<CharacterConfig>> {
             backgrounds.extend(char_bg);
         }
 
-        if backgrounds.is_empty() {
+        // 配置了后备背景时可以不使用背景图片，直接以纯色画布作为背景
+        let fallback_background = raw_character
+            .fallback_background
+            .or_else(|| template.fallback_background.clone());
+        if backgrounds.is_empty()
+            && fallback_background.is_none()
+            && raw_character.generated_background.is_none()
+        {
             bail!("角色 '{}' 缺少 backgrounds 配置", id);
         }
 
         backgrounds.sort_unstable();
         backgrounds.dedup();
 
+        let background_selection = raw_character
+            .background_selection
+            .or_else(|| template.background_selection.clone())
+            .unwrap_or_default();
+
         let font = raw_character
             .font
             .or_else(|| template.font.clone())
@@ -130,29 +340,195 @@ fn load_data(content: &str) -> Result<Vec<CharacterConfig>> {
 
         let primary_color = raw_character
             .primary_color
-            .or_else(|| template.primary_color.clone())
-            .map_or(BLACK, |c| c.to_rgba(BLACK));
-
-        let mut objects = template.objects.clone().unwrap_or_else(Vec::new);
+            .or_else(|| template.primary_color.clone());
+        let auto_primary_color =
+            matches!(&primary_color, Some(ColorInput::Literal(s)) if s == "auto");
+        // 自动主题颜色在生成时才能确定，此前按黑色处理
+        let primary_color = match primary_color {
+            Some(color) if !auto_primary_color => color.to_rgba(BLACK),
+            _ => BLACK,
+        };
+
+        let mut objects = template.objects.clone().unwrap_or_default();
         if let Some(mut char_objects) = raw_character.objects {
             objects.append(&mut char_objects);
         }
 
-        let textarea = raw_character
-            .textarea
-            .or_else(|| template.textarea.clone())
-            .ok_or_else(|| anyhow!("角色 '{}' 缺少 textarea 配置", id))?;
+        let textarea = match raw_character.textarea {
+            Some(textarea) => textarea,
+            None => template
+                .textarea
+                .as_ref()
+                .ok_or_else(|| anyhow!("角色 '{}' 缺少 textarea 配置", id))?
+                .resolve()
+                .with_context(|| format!("角色 '{}' 的 textarea 配置无效", id))?,
+        };
+        let context_textarea = match raw_character.context_textarea {
+            Some(context_textarea) => Some(context_textarea),
+            None => template
+                .context_textarea
+                .as_ref()
+                .map(TextAreaFields::resolve)
+                .transpose()
+                .with_context(|| format!("角色 '{}' 的 context_textarea 配置无效", id))?,
+        };
+        for area in std::iter::once(&textarea).chain(&context_textarea) {
+            if let Some((open, close)) = &area.highlight_delimiters
+                && (open.is_empty() || close.is_empty())
+            {
+                bail!("角色 '{}' 的 highlight_delimiters 不能为空", id);
+            }
+            if area.max_lines == Some(0) {
+                bail!("角色 '{}' 的 max_lines 必须大于 0", id);
+            }
+        }
+        let lengths = [&textarea]
+            .into_iter()
+            .chain(&context_textarea)
+            .flat_map(|area| area.position.iter().chain(&area.size))
+            .chain(objects.iter().flat_map(|object| object.position()));
+        for length in lengths {
+            if !length.is_valid() {
+                bail!("角色 '{}' 的位置或大小格式无效，应为整数或百分比字符串", id);
+            }
+        }
 
         result.push(CharacterConfig {
             id,
             name: raw_character.name,
             backgrounds,
+            background_selection,
             font,
+            emoji_font: raw_character
+                .emoji_font
+                .or_else(|| template.emoji_font.clone()),
             primary_color,
+            auto_primary_color,
             objects,
             textarea,
+            context_textarea,
+            anchor_to_content: raw_character
+                .anchor_to_content
+                .or(template.anchor_to_content)
+                .unwrap_or(false),
+            output_size: raw_character.output_size.or(template.output_size),
+            output_fit: raw_character
+                .output_fit
+                .or(template.output_fit)
+                .unwrap_or_default(),
+            corner_radius: raw_character
+                .corner_radius
+                .or(template.corner_radius)
+                .unwrap_or(0),
+            cooldown_ms: raw_character.cooldown_ms.or(template.cooldown_ms),
+            caption: raw_character.caption.or_else(|| template.caption.clone()),
+            header: raw_character.header.or_else(|| template.header.clone()),
+            border: raw_character.border.or_else(|| template.border.clone()),
+            fallback_background,
+            generated_background: raw_character.generated_background,
+            hotkey: raw_character.hotkey,
         });
     }
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn load(config: serde_json::Value) -> CharacterConfig {
+        let config: DataConfig = serde_json::from_value(config).unwrap();
+        load_data(config).unwrap().remove(0)
+    }
+
+    #[test]
+    fn style_preset_is_used_when_template_is_empty() {
+        let character = load(json!({
+            "styles": {
+                "bubble": {
+                    "backgrounds": ["bubble.png"],
+                    "font": "bubble.ttf",
+                    "textarea": { "position": [1, 2], "size": [30, 40], "font_color": "white" }
+                }
+            },
+            "characters": { "a": { "name": "A", "style": "bubble" } }
+        }));
+        assert_eq!(character.font, "bubble.ttf");
+        assert_eq!(character.textarea.pixel_position(), [1, 2]);
+        assert_eq!(character.textarea.pixel_size(), [30, 40]);
+    }
+
+    #[test]
+    fn template_overrides_style_preset_field_by_field() {
+        let character = load(json!({
+            "template": {
+                "backgrounds": ["template.png"],
+                "font": "template.ttf",
+                "textarea": { "position": [5, 6], "max_font_size": 20 }
+            },
+            "styles": {
+                "bubble": {
+                    "backgrounds": ["bubble.png"],
+                    "font": "bubble.ttf",
+                    "corner_radius": 8,
+                    "textarea": {
+                        "position": [1, 2],
+                        "size": [30, 40],
+                        "font_color": "white",
+                        "max_font_size": 10,
+                        "line_spacing": 0.5
+                    }
+                }
+            },
+            "characters": { "a": { "name": "A", "style": "bubble" } }
+        }));
+        assert_eq!(character.font, "template.ttf");
+        assert_eq!(character.corner_radius, 8);
+        assert_eq!(character.backgrounds, ["bubble.png", "template.png"]);
+        // 模板只覆盖其设置的字段，其余字段取自样式预设
+        assert_eq!(character.textarea.pixel_position(), [5, 6]);
+        assert_eq!(character.textarea.max_font_size, Some(20));
+        assert_eq!(character.textarea.pixel_size(), [30, 40]);
+        assert_eq!(character.textarea.line_spacing, 0.5);
+    }
+
+    #[test]
+    fn character_overrides_template_and_style_preset() {
+        let character = load(json!({
+            "template": { "font": "template.ttf", "corner_radius": 4 },
+            "styles": {
+                "bubble": {
+                    "backgrounds": ["bubble.png"],
+                    "font": "bubble.ttf",
+                    "corner_radius": 8,
+                    "textarea": { "position": [1, 2], "size": [30, 40], "font_color": "white" }
+                }
+            },
+            "characters": {
+                "a": {
+                    "name": "A",
+                    "style": "bubble",
+                    "font": "a.ttf",
+                    "textarea": { "position": [7, 8], "size": [9, 10], "font_color": "black" }
+                }
+            }
+        }));
+        assert_eq!(character.font, "a.ttf");
+        assert_eq!(character.corner_radius, 4);
+        assert_eq!(character.textarea.pixel_position(), [7, 8]);
+        assert_eq!(character.textarea.pixel_size(), [9, 10]);
+    }
+
+    #[test]
+    fn incomplete_merged_textarea_is_rejected() {
+        let config: DataConfig = serde_json::from_value(json!({
+            "template": { "backgrounds": ["a.png"], "font": "a.ttf", "textarea": { "size": [1, 1] } },
+            "characters": { "a": { "name": "A" } }
+        }))
+        .unwrap();
+        assert!(load_data(config).is_err());
+    }
+}
@@ -1,18 +1,174 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::Cursor;
+use std::sync::Arc;
 
-use ab_glyph::FontVec;
+use ab_glyph::{Font, FontVec, GlyphImageFormat, PxScaleFont, ScaleFont, point};
 use anyhow::{Result, anyhow};
-use image::{ImageFormat, Rgba, RgbaImage, imageops};
-use imageproc::drawing::draw_text_mut;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{
+    ExtendedColorType, ImageEncoder, ImageFormat, Pixel, Rgb, RgbImage, Rgba, RgbaImage, imageops,
+};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut, text_size};
+use imageproc::filter::gaussian_blur_f32;
+use imageproc::geometric_transformations::{Interpolation, rotate_about_center};
+use imageproc::rect::Rect;
+use rand::Rng;
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::data::{HorizontalAlign, ObjectConfig, TextAreaConfig, VerticalAlign};
+use crate::color_profile::srgb_icc_profile;
+use crate::data::{
+    BLACK, BackgroundSelection, BlendMode, BorderConfig, BoxFill, CharacterConfig, ColorInput,
+    FallbackBackground, GradientDirection, HeaderConfig, HorizontalAlign, Length, ObjectConfig,
+    OutputFit, TextAreaConfig, VerticalAlign, WHITE, WritingMode,
+};
 use crate::data_manager::DataManager;
-use crate::resource_loader::{load_font, load_random_image};
-use crate::textarea::{get_scaled_font, prepare_textarea};
+use crate::resource_loader::{choose_weighted, filter_by_aspect_ratio};
+use crate::textarea::{
+    FitConstraints, ITALIC_SHEAR, PreparedTextarea, ScaledFonts, TextFonts, TextSegment,
+    bold_extra, cluster_advance_at, get_scaled_font, kerning, measure_text, prepare_textarea,
+    ruby_fonts, usable_region,
+};
 
 // 压缩保守系数
 const CONSERVATIVE_FACTOR: f32 = 0.9;
+// 压缩最多尝试的次数
+const MAX_COMPRESS_ITERATIONS: usize = 8;
+// 压缩时图片短边的最小值
+const MIN_COMPRESS_DIMENSION: u32 = 16;
+// 压缩时 JPEG 质量的下限与每次降低的步长
+const MIN_JPEG_QUALITY: u8 = 40;
+const JPEG_QUALITY_STEP: u8 = 10;
+// 自动对比度调整的最低对比度
+const MIN_CONTRAST_RATIO: f32 = 3.0;
+// 与黑色和白色对比度相同的相对亮度，作为 `"auto-contrast"` 的默认阈值
+const DEFAULT_CONTRAST_THRESHOLD: f32 = 0.179;
+
+fn relative_luminance(color: Rgba<u8>) -> f32 {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let [r, g, b, _] = color.0;
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+fn contrast_ratio(a: f32, b: f32) -> f32 {
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// 计算图片指定区域的平均相对亮度，区域超出图片的部分会被忽略
+fn average_luminance(image: &RgbaImage, x: i32, y: i32, width: u32, height: u32) -> Option<f32> {
+    let x1 = x.clamp(0, image.width() as i32) as u32;
+    let y1 = y.clamp(0, image.height() as i32) as u32;
+    let x2 = (x + width as i32).clamp(0, image.width() as i32) as u32;
+    let y2 = (y + height as i32).clamp(0, image.height() as i32) as u32;
+    if x1 >= x2 || y1 >= y2 {
+        return None;
+    }
+
+    // 按步长采样以避免遍历大区域的所有像素
+    let step = ((x2 - x1).max(y2 - y1) / 64).max(1) as usize;
+    let mut total = 0.0;
+    let mut count = 0;
+    for py in (y1..y2).step_by(step) {
+        for px in (x1..x2).step_by(step) {
+            total += relative_luminance(*image.get_pixel(px, py));
+            count += 1;
+        }
+    }
+
+    Some(total / count as f32)
+}
+
+/// 文字颜色与背景对比度不足时，改用黑色或白色中对比度更高者
+fn ensure_contrast(color: Rgba<u8>, background_luminance: f32) -> Rgba<u8> {
+    if contrast_ratio(relative_luminance(color), background_luminance) >= MIN_CONTRAST_RATIO {
+        return color;
+    }
+
+    if contrast_ratio(1.0, background_luminance) >= contrast_ratio(0.0, background_luminance) {
+        WHITE
+    } else {
+        BLACK
+    }
+}
+
+/// 计算文字区域的文字颜色，`"auto-contrast"` 与 `auto_contrast` 均按区域内背景的平均亮度调整，
+/// 区域不在图片内时 `"auto-contrast"` 使用深色
+fn textarea_font_color(
+    image: &RgbaImage,
+    config: &TextAreaConfig,
+    primary_color: Rgba<u8>,
+) -> Rgba<u8> {
+    let auto = config.font_color.is_auto_contrast();
+    if !auto && !config.auto_contrast {
+        return config.font_color.to_rgba(primary_color);
+    }
+
+    let [x, y] = config.pixel_position();
+    let [width, height] = config.pixel_size();
+    let luminance = average_luminance(image, x, y, width, height);
+    if auto {
+        let [dark, light] = config
+            .contrast_colors
+            .as_ref()
+            .map_or([BLACK, WHITE], |colors| {
+                colors.each_ref().map(|color| color.to_rgba(primary_color))
+            });
+        let threshold = config
+            .contrast_threshold
+            .unwrap_or(DEFAULT_CONTRAST_THRESHOLD);
+        if luminance.is_none_or(|luminance| luminance > threshold) {
+            dark
+        } else {
+            light
+        }
+    } else {
+        let color = config.font_color.to_rgba(primary_color);
+        luminance.map_or(color, |luminance| ensure_contrast(color, luminance))
+    }
+}
+
+/// 依次绘制阴影、描边与主体，`draw` 以给定颜色和偏移绘制一次，最后一次绘制主体时 `is_fill` 为真，
+/// `shadow_offset` 为 `None` 时不绘制阴影
+fn draw_with_effects<F>(
+    image: &mut RgbaImage,
+    color: Rgba<u8>,
+    shadow_offset: Option<(i32, i32)>,
+    stroke: Option<(Rgba<u8>, u32)>,
+    mut draw: F,
+) where
+    F: FnMut(&mut RgbaImage, Rgba<u8>, i32, i32, bool),
+{
+    // 绘制阴影
+    if let Some((dx, dy)) = shadow_offset {
+        draw(image, BLACK, dx, dy, false);
+    }
+
+    // 绘制描边，在描边宽度内的各个偏移处绘制
+    if let Some((stroke_color, width)) = stroke
+        && width > 0
+    {
+        let width = width as i32;
+        for dy in -width..=width {
+            for dx in -width..=width {
+                if (dx, dy) != (0, 0) && dx * dx + dy * dy <= width * width {
+                    draw(image, stroke_color, dx, dy, false);
+                }
+            }
+        }
+    }
+
+    // 绘制主体
+    draw(image, color, 0, 0, true);
+}
 
 #[allow(clippy::too_many_arguments)]
 fn draw_text_with_shadow(
@@ -27,209 +183,2275 @@ fn draw_text_with_shadow(
 ) {
     let scale = get_scaled_font(font, font_size).scale.y;
 
-    // 绘制阴影
-    let shadow_color = Rgba([0u8, 0u8, 0u8, 255u8]);
-    draw_text_mut(
+    draw_with_effects(
         image,
-        shadow_color,
-        x + shadow_offset.0,
-        y + shadow_offset.1,
-        scale,
-        font,
-        text,
+        color,
+        Some(shadow_offset),
+        None,
+        |image, color, dx, dy, _| {
+            draw_text_blended(image, color, x + dx, y + dy, scale, font, text);
+        },
     );
+}
 
-    // 绘制主文字
-    draw_text_mut(image, color, x, y, scale, font, text);
+/// 在临时图层上绘制阴影并进行高斯模糊后叠加到图片上，`bounds` 为文字的包围盒，
+/// `draw` 以给定偏移在图层上绘制一次文字
+fn draw_blurred_shadow<F>(
+    image: &mut RgbaImage,
+    bounds: (i32, i32, i32, i32),
+    offset: (i32, i32),
+    blur: u32,
+    draw: F,
+) where
+    F: FnOnce(&mut RgbaImage, i32, i32),
+{
+    // 高斯模糊在约 3 倍标准差外可以忽略，图层四周按此留出余量
+    let sigma = blur as f32 / 2.0;
+    let margin = (sigma * 3.0).ceil() as i32;
+    let (left, top, right, bottom) = bounds;
+    let layer_x = left + offset.0 - margin;
+    let layer_y = top + offset.1 - margin;
+    let mut layer = RgbaImage::new(
+        (right - left + margin * 2) as u32,
+        (bottom - top + margin * 2) as u32,
+    );
+    draw(&mut layer, offset.0 - layer_x, offset.1 - layer_y);
+    let layer = gaussian_blur_f32(&layer, sigma);
+    imageops::overlay(image, &layer, layer_x as i64, layer_y as i64);
 }
 
-fn draw_textarea(
+/// 以半透明混合的方式绘制圆角矩形，圆角边缘按覆盖率抗锯齿
+fn draw_box_fill(
     image: &mut RgbaImage,
-    text: &str,
-    font: &FontVec,
-    config: &TextAreaConfig,
+    rect: (i32, i32, i32, i32),
+    box_fill: &BoxFill,
     primary_color: Rgba<u8>,
 ) {
-    let x1 = config.position[0];
-    let y1 = config.position[1];
-    let x2 = x1 + config.size[0] as i32;
-    let y2 = y1 + config.size[1] as i32;
-    let normal_color = config.font_color.to_rgba(primary_color);
-    let highlight_color = config.highlight.as_ref().map(|c| c.to_rgba(primary_color));
+    let (left, top, right, bottom) = rect;
+    let left = left.max(0);
+    let top = top.max(0);
+    let right = right.min(image.width() as i32);
+    let bottom = bottom.min(image.height() as i32);
+    if left >= right || top >= bottom {
+        return;
+    }
 
-    // 准备文本区域
-    let prepared = prepare_textarea(
-        text,
-        font,
-        config.size[0],
-        config.size[1],
-        config.max_font_size,
-        config.line_spacing,
-    );
+    let mut color = box_fill.color.to_rgba(primary_color);
+    let alpha = color[3] as f32 * box_fill.opacity.clamp(0.0, 1.0);
+    let radius = (box_fill.corner_radius as f32)
+        .min((right - left) as f32 / 2.0)
+        .min((bottom - top) as f32 / 2.0);
+
+    for y in top..bottom {
+        for x in left..right {
+            // 像素中心到最近圆角圆心的距离决定覆盖率，圆角以外的像素完全覆盖
+            let px = x as f32 + 0.5;
+            let py = y as f32 + 0.5;
+            let cx = px.clamp(left as f32 + radius, right as f32 - radius);
+            let cy = py.clamp(top as f32 + radius, bottom as f32 - radius);
+            let distance = ((px - cx).powi(2) + (py - cy).powi(2)).sqrt();
+            let coverage = if distance == 0.0 {
+                1.0
+            } else {
+                (radius - distance + 0.5).clamp(0.0, 1.0)
+            };
+            if coverage <= 0.0 {
+                continue;
+            }
+
+            color[3] = (alpha * coverage).round() as u8;
+            image.get_pixel_mut(x as u32, y as u32).blend(&color);
+        }
+    }
+}
+
+/// 从右到左排列时成对的括号需镜像显示
+fn mirror_char(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        _ => c,
+    }
+}
+
+/// 行在文本块内的起始横坐标，从右到左排列的行中左右对齐方式对调，两端对齐的行按起始方向对齐
+fn line_x(
+    align: &HorizontalAlign,
+    block_x: i32,
+    block_width: i32,
+    line_width: i32,
+    is_rtl: bool,
+) -> i32 {
+    match (align, is_rtl) {
+        (HorizontalAlign::Left | HorizontalAlign::Justify, false)
+        | (HorizontalAlign::Right, true) => block_x,
+        (HorizontalAlign::Center, _) => block_x + (block_width - line_width) / 2,
+        (HorizontalAlign::Right, false)
+        | (HorizontalAlign::Left | HorizontalAlign::Justify, true) => {
+            block_x + block_width - line_width
+        }
+    }
+}
+
+/// 两端对齐时将行内多余的长度依次分配到各个可加宽的位置
+struct Justification {
+    opportunities: Vec<(usize, usize)>,
+    extra: i32,
+    next: usize,
+}
+
+impl Justification {
+    /// 各段末行与没有可加宽位置的行不加宽
+    fn new(
+        prepared: &PreparedTextarea,
+        index: usize,
+        align: &HorizontalAlign,
+        length: i32,
+    ) -> Self {
+        let line = &prepared.lines[index];
+        let is_paragraph_end = prepared
+            .lines
+            .get(index + 1)
+            .is_none_or(|next| next.is_paragraph_start);
+        let opportunities = match align {
+            HorizontalAlign::Justify if !is_paragraph_end => line.justify_opportunities(),
+            _ => Vec::new(),
+        };
+        let extra = match opportunities.is_empty() {
+            true => 0,
+            false => (length - line.width as i32).max(0),
+        };
+        Justification {
+            opportunities,
+            extra,
+            next: 0,
+        }
+    }
+
+    /// 放置文本段 `segment_index` 中偏移 `offset` 处的文字之前需要加宽的长度
+    fn gap_before(&mut self, segment_index: usize, offset: usize) -> i32 {
+        let count = self.opportunities.len() as i32;
+        let mut gap = 0;
+        while let Some(&opportunity) = self.opportunities.get(self.next)
+            && opportunity <= (segment_index, offset)
+        {
+            let index = self.next as i32;
+            gap += self.extra * (index + 1) / count - self.extra * index / count;
+            self.next += 1;
+        }
+        gap
+    }
+
+    /// 在可加宽的位置进一步切分文本段的各部分，并给出各部分在文本段中的字节偏移
+    fn split<'a>(
+        &self,
+        segment_index: usize,
+        segment: &'a TextSegment,
+        pieces: Vec<(&'a str, bool)>,
+    ) -> Vec<(usize, &'a str, bool)> {
+        let mut result = Vec::new();
+        for (piece, emoji) in pieces {
+            // 各部分均为文本段文字的切片
+            let piece_start = piece.as_ptr() as usize - segment.text.as_ptr() as usize;
+            let end = piece_start + piece.len();
+            let mut start = piece_start;
+            for &(_, offset) in self.opportunities.iter().filter(|&&(index, offset)| {
+                index == segment_index && offset > piece_start && offset < end
+            }) {
+                result.push((start, &segment.text[start..offset], emoji));
+                start = offset;
+            }
+            result.push((start, &segment.text[start..end], emoji));
+        }
+        result
+    }
+}
+
+/// emoji 字体中字符的彩色位图(CBDT/sbix)，按字号缩放，返回位图相对于绘制位置的偏移与位图
+fn emoji_bitmap(scaled_font: PxScaleFont<&FontVec>, c: char) -> Option<(i32, i32, RgbaImage)> {
+    let pixels_per_em = scaled_font.scale_factor().vertical * scaled_font.font.units_per_em()?;
+    let raster = scaled_font
+        .font
+        .glyph_raster_image2(scaled_font.glyph_id(c), pixels_per_em.round() as u16)?;
+    let bitmap = match raster.format {
+        GlyphImageFormat::Png => image::load_from_memory_with_format(raster.data, ImageFormat::Png)
+            .ok()?
+            .to_rgba8(),
+        GlyphImageFormat::BitmapPremulBgra32 => {
+            let pixels = raster
+                .data
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let [b, g, r, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                    let unpremultiply = |c: u8| match a {
+                        0 => 0,
+                        _ => (c as u32 * 255 / a as u32).min(255) as u8,
+                    };
+                    [unpremultiply(r), unpremultiply(g), unpremultiply(b), a]
+                })
+                .collect();
+            RgbaImage::from_raw(raster.width.into(), raster.height.into(), pixels)?
+        }
+        _ => return None,
+    };
+
+    let ratio = pixels_per_em / raster.pixels_per_em.max(1) as f32;
+    let width = (bitmap.width() as f32 * ratio).round().max(1.0) as u32;
+    let height = (bitmap.height() as f32 * ratio).round().max(1.0) as u32;
+    let bitmap = imageops::resize(&bitmap, width, height, imageops::FilterType::Triangle);
+
+    // 位图原点为其左下角相对于基线的偏移(向上为正)，基线位于绘制位置下方 ascent 处
+    let x = (raster.origin.x * ratio).round() as i32;
+    let y = (scaled_font.ascent() - raster.origin.y * ratio).round() as i32 - height as i32;
+    Some((x, y, bitmap))
+}
+
+/// 以与 `draw_text_mut` 相同的排版绘制文字，按覆盖率以非预乘透明度混合到图片上，
+/// 在透明图层上绘制时边缘像素保留文字颜色，不会因与透明黑色加权而变暗
+fn draw_text_blended(
+    image: &mut RgbaImage,
+    color: Rgba<u8>,
+    x: i32,
+    y: i32,
+    scale: f32,
+    font: &FontVec,
+    text: &str,
+) {
+    let scaled_font = font.as_scaled(scale);
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph_id = scaled_font.glyph_id(c);
+        let glyph = glyph_id.with_scale_and_position(scale, point(caret, scaled_font.ascent()));
+        caret += scaled_font.h_advance(glyph_id);
+        let Some(outlined) = scaled_font.outline_glyph(glyph) else {
+            continue;
+        };
+        if let Some(previous) = previous {
+            caret += scaled_font.kern(glyph_id, previous);
+        }
+        previous = Some(glyph_id);
+
+        let bounds = outlined.px_bounds();
+        let (left, top) = (
+            x + bounds.min.x.round() as i32,
+            y + bounds.min.y.round() as i32,
+        );
+        outlined.draw(|gx, gy, coverage| {
+            let (px, py) = (left + gx as i32, top + gy as i32);
+            if (0..width).contains(&px) && (0..height).contains(&py) {
+                let alpha = (coverage.clamp(0.0, 1.0) * color[3] as f32).round() as u8;
+                image
+                    .get_pixel_mut(px as u32, py as u32)
+                    .blend(&Rgba([color[0], color[1], color[2], alpha]));
+            }
+        });
+    }
+}
+
+/// 绘制错切后的文字以模拟斜体，先绘制覆盖率蒙版，再逐行按到基线的距离平移并插值混合
+fn draw_text_sheared(
+    image: &mut RgbaImage,
+    color: Rgba<u8>,
+    x: i32,
+    y: i32,
+    font: PxScaleFont<&FontVec>,
+    text: &str,
+) {
+    let (width, height) = text_size(font.scale, font.font, text);
+    if width == 0 || height == 0 {
+        return;
+    }
+    let mut mask = RgbaImage::new(width, height);
+    draw_text_mut(&mut mask, WHITE, 0, 0, font.scale, font.font, text);
+
+    let coverage = |col: i64, row: u32| -> f32 {
+        if (0..width as i64).contains(&col) {
+            mask.get_pixel(col as u32, row)[3] as f32 / 255.0
+        } else {
+            0.0
+        }
+    };
+    let ascent = font.ascent();
+    for row in 0..height {
+        let image_y = y as i64 + row as i64;
+        if !(0..image.height() as i64).contains(&image_y) {
+            continue;
+        }
+        let shift = (ascent - row as f32 - 0.5) * ITALIC_SHEAR;
+        let whole = shift.floor();
+        let fraction = shift - whole;
+        for col in 0..=width as i64 {
+            let image_x = x as i64 + whole as i64 + col;
+            if !(0..image.width() as i64).contains(&image_x) {
+                continue;
+            }
+            let value = coverage(col, row) * (1.0 - fraction) + coverage(col - 1, row) * fraction;
+            if value > 0.0 {
+                let alpha = (value * color[3] as f32).round() as u8;
+                image
+                    .get_pixel_mut(image_x as u32, image_y as u32)
+                    .blend(&Rgba([color[0], color[1], color[2], alpha]));
+            }
+        }
+    }
+}
+
+/// 以位图的不透明度填充单一颜色，用于绘制彩色位图的阴影与描边
+fn silhouette(bitmap: &RgbaImage, color: Rgba<u8>) -> RgbaImage {
+    RgbaImage::from_fn(bitmap.width(), bitmap.height(), |x, y| {
+        let alpha = bitmap.get_pixel(x, y)[3] as u32 * color[3] as u32 / 255;
+        Rgba([color[0], color[1], color[2], alpha as u8])
+    })
+}
+
+/// 按使用的字体切分文字，emoji 逐字切分以便分别绘制彩色位图，设置了字间距或模拟粗体时
+/// 所有字符均逐字切分，制表符总是单独切分，其宽度取决于所在位置；
+/// 有字距调整的字符对也在其间切分，由排版自行计入字距，避免绘制时按相反的顺序再次调整
+fn font_pieces<'a>(segment: &'a TextSegment, fonts: &ScaledFonts) -> Vec<(&'a str, bool)> {
+    let mut pieces = Vec::new();
+    for (piece, emoji) in fonts.split(&segment.text) {
+        if emoji || fonts.letter_spacing != 0 || segment.bold {
+            pieces.extend(
+                piece
+                    .char_indices()
+                    .map(|(offset, c)| (&piece[offset..offset + c.len_utf8()], emoji)),
+            );
+            continue;
+        }
+        let mut start = 0;
+        let mut prev: Option<char> = None;
+        for (offset, c) in piece.char_indices() {
+            let split = c == '\t'
+                || prev.is_some_and(|p| {
+                    p == '\t' || kerning(fonts, p, c) != 0 || kerning(fonts, c, p) != 0
+                });
+            if split && offset > start {
+                pieces.push((&piece[start..offset], false));
+                start = offset;
+            }
+            prev = Some(c);
+        }
+        if start < piece.len() {
+            pieces.push((&piece[start..], false));
+        }
+    }
+    pieces
+}
+
+/// 各段文字占据区域的包围盒，没有文字时返回 `None`
+fn runs_bounds(runs: &[PlacedRun]) -> Option<(i32, i32, i32, i32)> {
+    runs.iter()
+        .map(|run| run.rect)
+        .fold(None, |bounds, (x, y, w, h)| {
+            let (left, top, right, bottom) = bounds.unwrap_or((x, y, x, y));
+            Some((
+                left.min(x),
+                top.min(y),
+                right.max(x + w as i32),
+                bottom.max(y + h as i32),
+            ))
+        })
+}
+
+/// 一段文字的绘制方式，文字与其修饰线一同绘制，使相邻文本段的线条首尾相接
+struct RunPainter<'a> {
+    text: &'a str,
+    font: PxScaleFont<&'a FontVec>,
+    /// 彩色 emoji 位图及其相对位置，阴影与描边使用其轮廓，没有位图时按字形轮廓绘制
+    bitmap: Option<(i32, i32, RgbaImage)>,
+    decorations: Vec<Rect>,
+    bold_offset: i32,
+    italic: bool,
+    origin: (i32, i32),
+}
+
+impl RunPainter<'_> {
+    fn paint(&self, image: &mut RgbaImage, color: Rgba<u8>, dx: i32, dy: i32, is_fill: bool) {
+        let (x, y) = self.origin;
+        match &self.bitmap {
+            Some((offset_x, offset_y, bitmap)) => {
+                let (x, y) = ((x + offset_x + dx) as i64, (y + offset_y + dy) as i64);
+                if is_fill {
+                    imageops::overlay(image, bitmap, x, y);
+                } else {
+                    imageops::overlay(image, &silhouette(bitmap, color), x, y);
+                }
+            }
+            None => {
+                for bx in 0..=self.bold_offset {
+                    let (x, y) = (x + dx + bx, y + dy);
+                    if self.italic {
+                        draw_text_sheared(image, color, x, y, self.font, self.text);
+                    } else {
+                        draw_text_blended(
+                            image,
+                            color,
+                            x,
+                            y,
+                            self.font.scale.y,
+                            self.font.font,
+                            self.text,
+                        );
+                    }
+                }
+            }
+        }
+        for rect in &self.decorations {
+            let rect =
+                Rect::at(rect.left() + dx, rect.top() + dy).of_size(rect.width(), rect.height());
+            draw_filled_rect_mut(image, rect, color);
+        }
+    }
+}
+
+/// 排版后待绘制的一段文字，`origin` 为绘制文字的位置，`rect` 为其占据的区域
+struct PlacedRun<'a> {
+    segment: &'a TextSegment,
+    text: Cow<'a, str>,
+    /// 是否使用 emoji 字体绘制
+    emoji: bool,
+    /// 是否为文本段的注音，注音不绘制修饰线与粗斜体
+    is_ruby: bool,
+    origin: (i32, i32),
+    rect: (i32, i32, u32, u32),
+}
+
+/// 横排时逐行放置文本段，行内各文本段基线对齐，超出区域底部的行不再放置
+fn place_horizontal<'a>(
+    prepared: &'a PreparedTextarea,
+    config: &TextAreaConfig,
+    region: ([i32; 2], [u32; 2]),
+    fonts: &ScaledFonts,
+) -> Vec<PlacedRun<'a>> {
+    let ([x1, y1], [width, height]) = region;
+    let x2 = x1 + width as i32;
+    let y2 = y1 + height as i32;
 
     // 垂直对齐
     let y_start = match &config.valign {
         VerticalAlign::Top => y1,
-        VerticalAlign::Middle => y1 + (config.size[1] as i32 - prepared.block_height as i32) / 2,
+        VerticalAlign::Middle => y1 + (height as i32 - prepared.block_height as i32) / 2,
         VerticalAlign::Bottom => y2 - prepared.block_height as i32,
     };
 
-    // 绘制每一行
+    // 文本块整体对齐，未指定时以整个区域作为文本块
+    let (block_x, block_width) = match &config.block_align {
+        Some(block_align) => {
+            let block_width = prepared.block_width as i32;
+            let block_x = match block_align {
+                HorizontalAlign::Left | HorizontalAlign::Justify => x1,
+                HorizontalAlign::Center => x1 + (width as i32 - block_width) / 2,
+                HorizontalAlign::Right => x2 - block_width,
+            };
+            (block_x, block_width)
+        }
+        None => (x1, width as i32),
+    };
+
+    let mut runs: Vec<PlacedRun> = Vec::new();
     let mut y = y_start;
-    for line in &prepared.lines {
-        let mut line_width = 0;
-        for (_, width) in line {
-            line_width += *width as i32;
+    for (index, line) in prepared.lines.iter().enumerate() {
+        if index > 0 && line.is_paragraph_start {
+            y += prepared.paragraph_gap as i32;
+            if y >= y2 {
+                break;
+            }
         }
+        // 行内水平对齐，从右到左排列的行自行尾向左放置，两端对齐的行计入加宽后的宽度
+        let mut justification = Justification::new(prepared, index, &config.align, block_width);
+        let line_width = line.width as i32 + justification.extra;
+        let line_start = line_x(&config.align, block_x, block_width, line_width, line.is_rtl);
+        let baseline = y + line.ascent.round() as i32;
+        let mut advance = line.indent as i32;
+        // 两端对齐累计加宽的长度，制表位仍按加宽前的位置计算
+        let mut stretch = 0;
+        let mut open_italic = 0;
 
-        // 水平对齐
-        let mut x = match &config.align {
-            HorizontalAlign::Left => x1,
-            HorizontalAlign::Center => x1 + (config.size[0] as i32 - line_width) / 2,
-            HorizontalAlign::Right => x2 - line_width,
-        };
+        for (segment_index, (segment, _)) in line.segments.iter().enumerate() {
+            let fonts = &fonts.for_segment(segment);
+            // 斜体文字之后留出其顶部伸出的宽度
+            if segment.italic {
+                open_italic = fonts.italic_overhang;
+            } else {
+                advance += std::mem::take(&mut open_italic) as i32;
+            }
+            let gap = justification.gap_before(segment_index, 0);
+            advance += gap;
+            stretch += gap;
+            let top = baseline - fonts.main.ascent().round() as i32;
+            let box_height = fonts.main.height().ceil() as u32;
+            let segment_start = advance;
+            let mut prev_char = None;
 
-        // 绘制每个文本段
-        for (segment, segment_width) in line {
-            if !segment.text.is_empty() {
-                let color = if segment.is_highlighted
-                    && let Some(hl_color) = highlight_color
+            let pieces = justification.split(segment_index, segment, font_pieces(segment, fonts));
+            for (offset, piece, emoji) in pieces {
+                let gap = justification.gap_before(segment_index, offset);
+                advance += gap;
+                stretch += gap;
+                // 段内加宽的部分归入前一部分，使修饰线保持连续
+                if let Some(last) = runs
+                    .last_mut()
+                    .filter(|run| std::ptr::eq(run.segment, segment))
                 {
-                    hl_color
+                    let (x, _, width, _) = &mut last.rect;
+                    if line.is_rtl {
+                        *x -= gap;
+                    }
+                    *width += gap as u32;
+                }
+                // 相邻两段之间的字距调整
+                if let (Some(prev), Some(c)) = (prev_char, piece.chars().next()) {
+                    advance += kerning(fonts, prev, c);
+                }
+                prev_char = piece.chars().last();
+                // 制表符只占位置，不绘制
+                let (piece_width, piece) = match piece {
+                    "\t" => (
+                        cluster_advance_at(fonts, piece, false, (advance - stretch) as u32),
+                        "",
+                    ),
+                    _ => (
+                        measure_text(piece, fonts, false)
+                            + bold_extra(fonts, segment, piece, false),
+                        piece,
+                    ),
+                };
+
+                // 从右到左排列时段内字符按视觉顺序反转
+                let (x, text) = if line.is_rtl {
+                    let x = line_start + line_width - advance - piece_width as i32;
+                    (
+                        x,
+                        Cow::Owned(
+                            piece
+                                .graphemes(true)
+                                .rev()
+                                .flat_map(|cluster| cluster.chars().map(mirror_char))
+                                .collect(),
+                        ),
+                    )
                 } else {
-                    normal_color
+                    (line_start + advance, Cow::Borrowed(piece))
                 };
+                runs.push(PlacedRun {
+                    segment,
+                    text,
+                    emoji,
+                    is_ruby: false,
+                    origin: (x, top),
+                    rect: (x, top, piece_width, box_height),
+                });
 
-                draw_text_with_shadow(
-                    image,
-                    &segment.text,
-                    x,
-                    y,
-                    font,
-                    prepared.font_size,
-                    color,
-                    config.shadow_offset,
-                );
+                advance += piece_width as i32;
+            }
 
-                x += *segment_width as i32;
+            // 注音在所注文字的正上方居中，较长时向两侧伸出
+            if let Some(ruby) = &segment.ruby {
+                let ruby_fonts = ruby_fonts(fonts, segment);
+                let ruby_width = measure_text(ruby, &ruby_fonts, false) as i32;
+                let ruby_height = ruby_fonts.main.height().ceil() as i32;
+                let segment_width = advance - segment_start;
+                let segment_x = if line.is_rtl {
+                    line_start + line_width - advance
+                } else {
+                    line_start + segment_start
+                };
+                let x = segment_x + (segment_width - ruby_width) / 2;
+                runs.push(PlacedRun {
+                    segment,
+                    text: Cow::Borrowed(ruby),
+                    emoji: false,
+                    is_ruby: true,
+                    origin: (x, top - ruby_height),
+                    rect: (x, top - ruby_height, ruby_width as u32, ruby_height as u32),
+                });
             }
         }
 
-        y += prepared.spaced_line_height as i32;
+        y += line.spaced_height as i32;
         if y >= y2 {
             break;
         }
     }
+    runs
 }
 
-fn compress_image(img: RgbaImage, target_size_bytes: usize) -> RgbaImage {
-    let (width, height) = img.dimensions();
+/// 竖排时逐列逐字放置，各列自右向左排列，超出区域左侧的列不再放置
+///
+/// 对齐方式按书写方向理解：`valign` 决定各列整体在区域中的水平位置(顶部对应右侧)，
+/// `block_align` 与 `align` 决定文本块与各列在垂直方向上的位置(左侧对应顶部)
+fn place_vertical<'a>(
+    prepared: &'a PreparedTextarea,
+    config: &TextAreaConfig,
+    region: ([i32; 2], [u32; 2]),
+    fonts: &ScaledFonts,
+) -> Vec<PlacedRun<'a>> {
+    let ([x1, y1], [width, height]) = region;
+    let x2 = x1 + width as i32;
+    let y2 = y1 + height as i32;
 
-    let mut buf = Vec::new();
-    if img
-        .write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)
-        .is_err()
-    {
-        return img;
-    }
+    let block_right = match &config.valign {
+        VerticalAlign::Top => x2,
+        VerticalAlign::Middle => x2 - (width as i32 - prepared.block_width as i32) / 2,
+        VerticalAlign::Bottom => x1 + prepared.block_width as i32,
+    };
 
-    let original_size = buf.len();
+    let (block_y, block_height) = match &config.block_align {
+        Some(block_align) => {
+            let block_height = prepared.block_height as i32;
+            let block_y = match block_align {
+                HorizontalAlign::Left | HorizontalAlign::Justify => y1,
+                HorizontalAlign::Center => y1 + (height as i32 - block_height) / 2,
+                HorizontalAlign::Right => y2 - block_height,
+            };
+            (block_y, block_height)
+        }
+        None => (y1, height as i32),
+    };
 
-    if original_size <= target_size_bytes {
-        return img;
-    }
+    let mut runs = Vec::new();
+    let mut right = block_right;
+    for (index, line) in prepared.lines.iter().enumerate() {
+        if index > 0 && line.is_paragraph_start {
+            right -= prepared.paragraph_gap as i32;
+            if right <= x1 {
+                break;
+            }
+        }
+        let column_width = line.height.ceil() as i32;
+        let left = right - column_width;
+        let mut justification = Justification::new(prepared, index, &config.align, block_height);
+        let column_length = line.width as i32 + justification.extra;
+        let column_top = match &config.align {
+            HorizontalAlign::Left | HorizontalAlign::Justify => block_y,
+            HorizontalAlign::Center => block_y + (block_height - column_length) / 2,
+            HorizontalAlign::Right => block_y + block_height - column_length,
+        };
+        let mut y = column_top + line.indent as i32;
+        let mut stretch = 0;
 
-    let size_ratio = (target_size_bytes as f32) / (original_size as f32);
-    let scale_factor = size_ratio.sqrt() * CONSERVATIVE_FACTOR;
+        for (segment_index, (segment, _)) in line.segments.iter().enumerate() {
+            let fonts = &fonts.for_segment(segment);
+            for (piece, emoji) in fonts.split(&segment.text) {
+                let scaled_font = fonts.get(emoji);
+                // 各部分均为文本段文字的切片
+                let piece_offset = piece.as_ptr() as usize - segment.text.as_ptr() as usize;
+                for (offset, c) in piece.char_indices() {
+                    let gap = justification.gap_before(segment_index, piece_offset + offset);
+                    y += gap;
+                    stretch += gap;
+                    let text = &piece[offset..offset + c.len_utf8()];
+                    let advance =
+                        cluster_advance_at(fonts, text, true, (y - column_top - stretch) as u32);
+                    let glyph_id = scaled_font.glyph_id(c);
 
-    let width = ((width as f32 * scale_factor) as u32).max(1);
-    let height = ((height as f32 * scale_factor) as u32).max(1);
+                    // 字形在列内水平居中，有纵向度量时将字形顶部对齐到上侧支距处
+                    let x = left
+                        + ((column_width as f32 - scaled_font.h_advance(glyph_id)) / 2.0).round()
+                            as i32;
+                    let top_offset = if scaled_font.v_advance(glyph_id) > 0.0 {
+                        scaled_font
+                            .outline_glyph(glyph_id.with_scale_and_position(
+                                scaled_font.scale,
+                                point(0.0, scaled_font.ascent()),
+                            ))
+                            .map_or(0, |glyph| {
+                                (scaled_font.v_side_bearing(glyph_id) - glyph.px_bounds().min.y)
+                                    .round() as i32
+                            })
+                    } else {
+                        0
+                    };
+
+                    runs.push(PlacedRun {
+                        segment,
+                        text: Cow::Borrowed(if c == '\t' { "" } else { text }),
+                        emoji,
+                        is_ruby: false,
+                        origin: (x, y + top_offset),
+                        rect: (left, y, column_width as u32, advance),
+                    });
+                    y += advance as i32;
+                }
+            }
+        }
 
-    imageops::resize(&img, width, height, imageops::FilterType::Lanczos3)
+        right -= line.spaced_height as i32;
+        if right <= x1 {
+            break;
+        }
+    }
+    runs
 }
 
-pub fn generate_image(
-    data_manager: &DataManager,
-    character_id: &str,
-    text: &str,
-    max_size: usize,
-    images: Option<&HashMap<String, Vec<String>>>,
-) -> Result<RgbaImage> {
-    let character_config = data_manager
-        .get_character(character_id)
-        .ok_or_else(|| anyhow!("角色 '{}' 不存在", character_id))?;
+/// 按混合模式将图片合成到目标图像上，混合结果按图片的透明度与原有颜色过渡，
+/// 目标像素透明的部分按普通模式合成
+fn blend_image(target: &mut RgbaImage, image: &RgbaImage, x: i32, y: i32, mode: BlendMode) {
+    for (px, py, source) in image.enumerate_pixels() {
+        let (tx, ty) = (x + px as i32, y + py as i32);
+        if tx < 0 || ty < 0 || tx >= target.width() as i32 || ty >= target.height() as i32 {
+            continue;
+        }
+        let source_alpha = source[3] as f32 / 255.0;
+        if source_alpha == 0.0 {
+            continue;
+        }
+        let backdrop = target.get_pixel_mut(tx as u32, ty as u32);
+        let backdrop_alpha = backdrop[3] as f32 / 255.0;
+        let alpha = source_alpha + backdrop_alpha * (1.0 - source_alpha);
+        for channel in 0..3 {
+            let s = source[channel] as f32 / 255.0;
+            let b = backdrop[channel] as f32 / 255.0;
+            let mixed = (1.0 - backdrop_alpha) * s + backdrop_alpha * mode.apply(b, s);
+            let value = (source_alpha * mixed + (1.0 - source_alpha) * backdrop_alpha * b) / alpha;
+            backdrop[channel] = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        backdrop[3] = (alpha * 255.0).round() as u8;
+    }
+}
 
-    let mut rng = rand::rng();
+/// 绕中心顺时针旋转图片，画布扩大到旋转后的包围盒，空出的部分透明
+fn rotate_object_image(image: &RgbaImage, degrees: f32) -> RgbaImage {
+    let theta = degrees.to_radians();
+    let (sin, cos) = (theta.sin().abs(), theta.cos().abs());
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let bounds_width = ((width * cos + height * sin).ceil() as u32).max(image.width());
+    let bounds_height = ((width * sin + height * cos).ceil() as u32).max(image.height());
 
-    let backgrounds = data_manager
-        .get_backgrounds(character_config)
-        .ok_or_else(|| anyhow!("角色 '{}' 没有可用的背景图片", character_id))?;
-    let mut image = load_random_image(&mut rng, &backgrounds)
-        .ok_or_else(|| anyhow!("无法加载角色 '{}' 的背景图片", character_id))?;
+    let mut canvas = RgbaImage::new(bounds_width, bounds_height);
+    imageops::replace(
+        &mut canvas,
+        image,
+        ((bounds_width - image.width()) / 2) as i64,
+        ((bounds_height - image.height()) / 2) as i64,
+    );
+    premultiply_alpha(&mut canvas, false);
+    let mut rotated =
+        rotate_about_center(&canvas, theta, Interpolation::Bilinear, Rgba([0, 0, 0, 0]));
+    premultiply_alpha(&mut rotated, true);
+    rotated
+}
 
-    let font_path = data_manager.get_font_path(character_config);
-    let font = load_font(&font_path)
-        .ok_or_else(|| anyhow!("无法加载角色 '{}' 的字体文件", character_id))?;
+/// 预乘或还原透明度，旋转与缩放插值时避免透明像素的颜色混入文字边缘
+fn premultiply_alpha(image: &mut RgbaImage, inverse: bool) {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        if alpha == 0 {
+            continue;
+        }
+        for channel in &mut pixel.0[..3] {
+            let value = *channel as u32;
+            *channel = if inverse {
+                (value * 255 / alpha).min(255)
+            } else {
+                value * alpha / 255
+            } as u8;
+        }
+    }
+}
 
-    for object in &character_config.objects {
-        match object {
-            ObjectConfig::Image { position, path, id } => {
-                let paths = if let Some(img_map) = images
-                    && let Some(img_id) = id
-                    && let Some(img_paths) = img_map.get(img_id)
-                {
-                    img_paths
-                } else {
-                    path.as_ref().ok_or_else(|| {
-                        anyhow!("图片对象必须指定 path 或者通过 id 在 images 参数中提供路径")
-                    })?
-                };
+/// 按倍数放大文字区域配置中以像素为单位的尺寸，用于超采样绘制
+fn scale_textarea(config: &TextAreaConfig, factor: u32) -> TextAreaConfig {
+    let scale = |value: u32| value * factor;
+    let mut scaled = config.clone();
+    scaled.position = config
+        .pixel_position()
+        .map(|value| Length::Pixels(value * factor as i32));
+    scaled.size = config
+        .pixel_size()
+        .map(|value| Length::Pixels(scale(value) as i32));
+    scaled.first_line_indent = config.first_line_indent * factor as i32;
+    scaled.max_font_size = config.max_font_size.map(scale);
+    scaled.min_font_size = config.min_font_size.map(scale);
+    scaled.shadow_offset = (
+        config.shadow_offset.0 * factor as i32,
+        config.shadow_offset.1 * factor as i32,
+    );
+    scaled.shadow_blur = scale(config.shadow_blur);
+    if let Some(stroke) = &mut scaled.stroke {
+        stroke.width = scale(stroke.width);
+    }
+    if let Some(box_fill) = &mut scaled.box_fill {
+        box_fill.padding = scale(box_fill.padding);
+        box_fill.corner_radius = scale(box_fill.corner_radius);
+    }
+    scaled
+}
 
-                let available_imgs = data_manager.get_images(character_config, paths);
-                if let Some(img) = load_random_image(&mut rng, &available_imgs) {
-                    imageops::overlay(&mut image, &img, position[0] as i64, position[1] as i64);
-                }
-            }
-            ObjectConfig::Text {
-                text,
-                position,
-                font_color,
-                font_size,
-            } => {
-                if !text.is_empty() {
-                    let color = font_color.to_rgba(character_config.primary_color);
-
-                    draw_text_with_shadow(
-                        &mut image,
-                        text,
-                        position[0],
-                        position[1],
-                        &font,
-                        *font_size,
-                        color,
-                        (2, 2),
-                    );
-                }
-            }
-        }
+fn draw_textarea(
+    image: &mut RgbaImage,
+    text: &str,
+    fonts: TextFonts,
+    config: &TextAreaConfig,
+    primary_color: Rgba<u8>,
+) {
+    let factor = config.supersample.max(1);
+    if config.rotation == 0.0 && factor == 1 {
+        draw_upright_textarea(image, text, fonts, config, primary_color);
+        return;
+    }
+
+    // 在单独的图层上居中绘制未旋转的文字区域，旋转时使用足以容纳旋转后区域的正方形图层
+    let [x, y] = config.pixel_position();
+    let [width, height] = config.pixel_size();
+    let (layer_width, layer_height) = if config.rotation == 0.0 {
+        (width, height)
+    } else {
+        let side = (width as f32).hypot(height as f32).ceil() as u32;
+        (side, side)
+    };
+    let local_position = [
+        ((layer_width - width) / 2) as i32,
+        ((layer_height - height) / 2) as i32,
+    ];
+
+    let mut local = config.clone();
+    local.position = local_position.map(Length::Pixels);
+    // 自动对比度按图片上实际的背景计算
+    if config.auto_contrast || config.font_color.is_auto_contrast() {
+        let color = textarea_font_color(image, config, primary_color);
+        local.font_color = ColorInput::RgbaArr(color.0);
+        local.auto_contrast = false;
     }
 
-    draw_textarea(
-        &mut image,
+    // 超采样时以放大后的尺寸绘制，再缩小到原尺寸
+    let mut layer = RgbaImage::new(layer_width * factor, layer_height * factor);
+    draw_upright_textarea(
+        &mut layer,
         text,
-        &font,
-        &character_config.textarea,
-        character_config.primary_color,
+        fonts,
+        &scale_textarea(&local, factor),
+        primary_color,
+    );
+    premultiply_alpha(&mut layer, false);
+    if factor > 1 {
+        layer = imageops::resize(&layer, layer_width, layer_height, FilterType::Lanczos3);
+    }
+    if config.rotation != 0.0 {
+        layer = rotate_about_center(
+            &layer,
+            config.rotation.to_radians(),
+            Interpolation::Bilinear,
+            Rgba([0, 0, 0, 0]),
+        );
+    }
+    premultiply_alpha(&mut layer, true);
+    imageops::overlay(
+        image,
+        &layer,
+        (x - local_position[0]) as i64,
+        (y - local_position[1]) as i64,
     );
+}
 
-    Ok(if max_size > 0 {
-        let max_size = if max_size > usize::MAX / 1024 {
-            usize::MAX
-        } else {
-            max_size * 1024
-        };
-        compress_image(image, max_size)
-    } else {
-        image
-    })
+/// 在区域大小的背景副本上绘制后再放回原图，文字、阴影与底板均不会落在区域之外
+fn draw_upright_textarea(
+    image: &mut RgbaImage,
+    text: &str,
+    fonts: TextFonts,
+    config: &TextAreaConfig,
+    primary_color: Rgba<u8>,
+) {
+    let [x, y] = config.pixel_position();
+    let [width, height] = config.pixel_size();
+    let left = x.clamp(0, image.width() as i32);
+    let top = y.clamp(0, image.height() as i32);
+    let right = (x + width as i32).clamp(0, image.width() as i32);
+    let bottom = (y + height as i32).clamp(0, image.height() as i32);
+    if left >= right || top >= bottom {
+        return;
+    }
+
+    let mut clip = imageops::crop_imm(
+        image,
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    )
+    .to_image();
+    let mut local = config.clone();
+    local.position = [x - left, y - top].map(Length::Pixels);
+    draw_unclipped_textarea(&mut clip, text, fonts, &local, primary_color);
+    imageops::replace(image, &clip, left as i64, top as i64);
+}
+
+fn draw_unclipped_textarea(
+    image: &mut RgbaImage,
+    text: &str,
+    fonts: TextFonts,
+    config: &TextAreaConfig,
+    primary_color: Rgba<u8>,
+) {
+    // 文字及其描边、阴影均需位于区域内
+    let region = usable_region(config);
+    // 区域过小(含扣除描边与阴影后)时不绘制，避免文字溢出到区域外
+    if region.1.contains(&0) {
+        return;
+    }
+
+    // 准备文本区域
+    let prepared = prepare_textarea(text, fonts, config, &FitConstraints::from_config(config));
+    let scaled_fonts = fonts.scaled(prepared.font_size, config);
+
+    let vertical = config.writing_mode == WritingMode::VerticalRl;
+    let runs = if vertical {
+        place_vertical(&prepared, config, region, &scaled_fonts)
+    } else {
+        place_horizontal(&prepared, config, region, &scaled_fonts)
+    };
+
+    // 文字背后的底板，大小为文字包围盒加上内边距并限制在区域内
+    if let Some(box_fill) = &config.box_fill
+        && let Some((left, top, right, bottom)) = runs_bounds(&runs)
+    {
+        let padding = box_fill.padding as i32;
+        let [region_x, region_y] = config.pixel_position();
+        let [region_width, region_height] = config.pixel_size();
+        let rect = (
+            (left - padding).max(region_x),
+            (top - padding).max(region_y),
+            (right + padding).min(region_x + region_width as i32),
+            (bottom + padding).min(region_y + region_height as i32),
+        );
+        draw_box_fill(image, rect, box_fill, primary_color);
+    }
+
+    let normal_color = textarea_font_color(image, config, primary_color);
+    let stroke = config
+        .stroke
+        .as_ref()
+        .map(|stroke| (stroke.color.to_rgba(primary_color), stroke.width));
+
+    // 横排时下划线位于基线下方，删除线位于字身框中部；竖排时分别位于列的右侧与中线，粗细随字号变化
+    // 横排时按文本段自身的字号计算位置，基线对齐的各段下划线位于同一高度
+    let thickness = (prepared.font_size as f32 / 16.0).round().max(1.0) as u32;
+    let decoration_rect = |(x, y, w, h): (i32, i32, u32, u32),
+                           underline: bool,
+                           scaled_font: PxScaleFont<&FontVec>| {
+        if vertical {
+            let offset = if underline {
+                w as i32 - thickness as i32
+            } else {
+                (w / 2) as i32 - thickness as i32 / 2
+            };
+            Rect::at(x + offset, y).of_size(thickness, h.max(1))
+        } else {
+            let offset = if underline {
+                scaled_font.ascent().round() as i32 + thickness as i32
+            } else {
+                ((scaled_font.ascent() - scaled_font.descent()) / 2.0).round() as i32
+                    - thickness as i32 / 2
+            };
+            Rect::at(x, y + offset).of_size(w.max(1), thickness)
+        }
+    };
+
+    let painters: Vec<(Rgba<u8>, RunPainter)> = runs
+        .iter()
+        .map(|run| {
+            // 未配置对应级别的高亮颜色时使用普通颜色
+            let color = run
+                .segment
+                .highlight
+                .zip(config.highlight.as_ref())
+                .and_then(|(level, highlight)| highlight.get(level))
+                .map_or(normal_color, |c| c.to_rgba(primary_color));
+            let run_fonts = if run.is_ruby {
+                ruby_fonts(&scaled_fonts, run.segment)
+            } else {
+                scaled_fonts.for_segment(run.segment)
+            };
+
+            let decorations: Vec<Rect> = [
+                (run.segment.underline, true),
+                (run.segment.strikethrough, false),
+            ]
+            .into_iter()
+            .filter(|&(enabled, _)| enabled && !run.is_ruby && run.rect.2 > 0 && run.rect.3 > 0)
+            .map(|(_, underline)| decoration_rect(run.rect, underline, run_fonts.main))
+            .collect();
+
+            let font = run_fonts.get(run.emoji);
+            let bitmap = run
+                .text
+                .chars()
+                .next()
+                .filter(|_| run.emoji)
+                .and_then(|c| emoji_bitmap(font, c));
+
+            // 模拟粗体时在错位距离内横向重复绘制，竖排时不占用额外的步进
+            let bold_offset = if run.segment.bold && !run.is_ruby {
+                run_fonts.bold_offset as i32
+            } else {
+                0
+            };
+
+            let painter = RunPainter {
+                text: &run.text,
+                font,
+                bitmap,
+                decorations,
+                bold_offset,
+                italic: run.segment.italic && !run.is_ruby && !vertical,
+                origin: run.origin,
+            };
+            (color, painter)
+        })
+        .collect();
+
+    // 模糊阴影需要对全部文字一同模糊，先单独绘制，之后不再绘制硬阴影
+    let shadow_offset = match runs_bounds(&runs) {
+        Some(bounds) if config.shadow_blur > 0 => {
+            draw_blurred_shadow(
+                image,
+                bounds,
+                config.shadow_offset,
+                config.shadow_blur,
+                |shadow, dx, dy| {
+                    for (_, painter) in &painters {
+                        painter.paint(shadow, BLACK, dx, dy, false);
+                    }
+                },
+            );
+            None
+        }
+        _ => Some(config.shadow_offset),
+    };
+
+    for (color, painter) in &painters {
+        draw_with_effects(
+            image,
+            *color,
+            shadow_offset,
+            stroke,
+            |image, color, dx, dy, is_fill| painter.paint(image, color, dx, dy, is_fill),
+        );
+    }
+}
+
+/// 按背景配置生成纯色或渐变画布
+fn background_canvas(fallback: &FallbackBackground, primary_color: Rgba<u8>) -> RgbaImage {
+    let [width, height] = fallback.size.map(|side| side.max(1));
+    let start = fallback.color.to_rgba(primary_color);
+    let Some(end) = fallback.end_color.as_ref() else {
+        return RgbaImage::from_pixel(width, height, start);
+    };
+    let end = end.to_rgba(primary_color);
+
+    let (w, h) = ((width - 1) as f32, (height - 1) as f32);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as f32, y as f32);
+        let t = match fallback.direction {
+            GradientDirection::Vertical => y / h,
+            GradientDirection::Horizontal => x / w,
+            // 沿对角线方向投影，使左上角为起点、右下角为终点
+            GradientDirection::Diagonal => (x * w + y * h) / (w * w + h * h),
+        };
+        let t = if t.is_finite() { t } else { 0.0 };
+        Rgba(std::array::from_fn(|channel| {
+            (start[channel] as f32 + (end[channel] as f32 - start[channel] as f32) * t).round()
+                as u8
+        }))
+    })
+}
+
+/// 提取图片的主色调：将不透明像素按每通道 4 位量化后统计，取出现最多的一组颜色的平均值，
+/// 图片完全透明时返回 `None`
+fn dominant_color(image: &RgbaImage) -> Option<Rgba<u8>> {
+    // 大图按步长抽样，抽样约 65536 个像素
+    let step = ((image.width() as u64 * image.height() as u64 / 65536) as f64)
+        .sqrt()
+        .max(1.0) as usize;
+    let mut buckets: HashMap<[u8; 3], (u32, [u64; 3])> = HashMap::new();
+    for y in (0..image.height()).step_by(step) {
+        for x in (0..image.width()).step_by(step) {
+            let [r, g, b, a] = image.get_pixel(x, y).0;
+            if a < 128 {
+                continue;
+            }
+            let (count, sum) = buckets.entry([r >> 4, g >> 4, b >> 4]).or_default();
+            *count += 1;
+            for (total, value) in sum.iter_mut().zip([r, g, b]) {
+                *total += value as u64;
+            }
+        }
+    }
+
+    // 数量相同时按量化后的颜色比较，保证结果不受哈希表遍历顺序影响
+    let (_, (count, sum)) = buckets
+        .into_iter()
+        .max_by_key(|(key, (count, _))| (*count, *key))?;
+    let [r, g, b] = sum.map(|total| (total / count as u64) as u8);
+    Some(Rgba([r, g, b, 255]))
+}
+
+/// 计算图片中非透明像素的包围盒，返回左上角坐标与尺寸，图片完全透明时返回 `None`
+pub(crate) fn content_bounds(image: &RgbaImage) -> Option<([i32; 2], [u32; 2])> {
+    let mut bounds: Option<[u32; 4]> = None;
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel[3] > 0 {
+            bounds = Some(match bounds {
+                Some([left, top, right, bottom]) => {
+                    [left.min(x), top.min(y), right.max(x), bottom.max(y)]
+                }
+                None => [x, y, x, y],
+            });
+        }
+    }
+    bounds.map(|[left, top, right, bottom]| {
+        (
+            [left as i32, top as i32],
+            [right - left + 1, bottom - top + 1],
+        )
+    })
+}
+
+/// 绘制包含头像与角色名称的标题栏，`overlay` 为 `false` 时将原图整体下移
+fn draw_header<T: Rng>(
+    rng: &mut T,
+    image: RgbaImage,
+    data_manager: &DataManager,
+    character_config: &CharacterConfig,
+    header: &HeaderConfig,
+    font: &FontVec,
+) -> RgbaImage {
+    let width = image.width();
+    let height = header.height;
+    let padding = height / 8;
+    let background_color = header
+        .background_color
+        .to_rgba(character_config.primary_color);
+    let mut bar = RgbaImage::from_pixel(width, height, background_color);
+
+    // 头像等比缩放到标题栏内
+    let avatars = data_manager.get_images(character_config, std::slice::from_ref(&header.avatar));
+    let mut name_x = padding;
+    if let Some(avatar) = data_manager.load_random_image(rng, &avatars) {
+        let size = height.saturating_sub(padding * 2).max(1);
+        let avatar = imageops::thumbnail(avatar.as_ref(), size, size);
+        imageops::overlay(
+            &mut bar,
+            &avatar,
+            padding as i64,
+            (height - avatar.height()) as i64 / 2,
+        );
+        name_x += avatar.width() + padding;
+    }
+
+    let scaled_font = get_scaled_font(font, (height / 2).max(1));
+    let name_color = header.name_color.to_rgba(character_config.primary_color);
+    draw_text_blended(
+        &mut bar,
+        name_color,
+        name_x as i32,
+        (height as i32 - scaled_font.height().ceil() as i32) / 2,
+        scaled_font.scale.y,
+        font,
+        &character_config.name,
+    );
+
+    if header.overlay {
+        let mut image = image;
+        imageops::overlay(&mut image, &bar, 0, 0);
+        image
+    } else {
+        let mut canvas = RgbaImage::new(width, image.height() + height);
+        imageops::replace(&mut canvas, &bar, 0, 0);
+        imageops::replace(&mut canvas, &image, 0, height as i64);
+        canvas
+    }
+}
+
+/// 将图片缩放到指定的输出尺寸
+fn fit_to_output(image: RgbaImage, size: [u32; 2], fit: OutputFit) -> RgbaImage {
+    let [width, height] = size;
+    let (src_width, src_height) = image.dimensions();
+    if width == 0 || height == 0 || (src_width, src_height) == (width, height) {
+        return image;
+    }
+
+    let filter = imageops::FilterType::Lanczos3;
+    let scale_x = width as f32 / src_width as f32;
+    let scale_y = height as f32 / src_height as f32;
+    let scaled_size = |scale: f32| {
+        (
+            ((src_width as f32 * scale).round() as u32).max(1),
+            ((src_height as f32 * scale).round() as u32).max(1),
+        )
+    };
+
+    match fit {
+        OutputFit::Stretch => imageops::resize(&image, width, height, filter),
+        OutputFit::Contain => {
+            let (scaled_width, scaled_height) = scaled_size(scale_x.min(scale_y));
+            let scaled = imageops::resize(&image, scaled_width, scaled_height, filter);
+            let mut canvas = RgbaImage::new(width, height);
+            imageops::overlay(
+                &mut canvas,
+                &scaled,
+                (width as i64 - scaled_width as i64) / 2,
+                (height as i64 - scaled_height as i64) / 2,
+            );
+            canvas
+        }
+        OutputFit::Cover => {
+            let (scaled_width, scaled_height) = scaled_size(scale_x.max(scale_y));
+            let scaled = imageops::resize(&image, scaled_width, scaled_height, filter);
+            imageops::crop_imm(
+                &scaled,
+                scaled_width.saturating_sub(width) / 2,
+                scaled_height.saturating_sub(height) / 2,
+                width,
+                height,
+            )
+            .to_image()
+        }
+    }
+}
+
+/// 沿图片边缘绘制边框，设置了圆角时边框随圆角弯曲，不内嵌时先向外扩展透明画布
+fn draw_border(
+    image: RgbaImage,
+    border: &BorderConfig,
+    inset: bool,
+    corner_radius: u32,
+    primary_color: Rgba<u8>,
+) -> RgbaImage {
+    let mut image = if inset {
+        image
+    } else {
+        let mut canvas = RgbaImage::new(
+            image.width() + border.width * 2,
+            image.height() + border.width * 2,
+        );
+        imageops::replace(
+            &mut canvas,
+            &image,
+            border.width as i64,
+            border.width as i64,
+        );
+        canvas
+    };
+
+    let (width, height) = image.dimensions();
+    let radius = corner_radius.min(width / 2).min(height / 2) as f32;
+    let start = border.color.to_rgba(primary_color);
+    let end = border
+        .end_color
+        .as_ref()
+        .map_or(start, |color| color.to_rgba(primary_color));
+    let (w, h) = (width as f32, height as f32);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+        // 像素中心到圆角矩形边缘的距离，位于圆角内时按到圆弧的距离计算
+        let (dx, dy) = (
+            (radius - cx).max(cx - (w - radius)),
+            (radius - cy).max(cy - (h - radius)),
+        );
+        let distance = if dx > 0.0 && dy > 0.0 {
+            radius - dx.hypot(dy)
+        } else {
+            cx.min(cy).min(w - cx).min(h - cy)
+        };
+        let coverage = (border.width as f32 - distance + 0.5).clamp(0.0, 1.0);
+        if coverage == 0.0 {
+            continue;
+        }
+        let t = if height > 1 {
+            y as f32 / (height - 1) as f32
+        } else {
+            0.0
+        };
+        let mut color = Rgba::from(std::array::from_fn(|channel| {
+            (start[channel] as f32 + (end[channel] as f32 - start[channel] as f32) * t).round()
+                as u8
+        }));
+        color[3] = (color[3] as f32 * coverage).round() as u8;
+        pixel.blend(&color);
+    }
+    image
+}
+
+/// 将图片四角裁剪为圆角，圆角外的像素变为透明，边缘按覆盖比例抗锯齿
+fn round_corners(image: &mut RgbaImage, radius: u32) {
+    let (width, height) = image.dimensions();
+    let radius = radius.min(width / 2).min(height / 2);
+    let r = radius as f32;
+    for y in 0..height {
+        // 到所在角圆心的纵向距离，不在角内的行跳过
+        let dy = match y {
+            y if y < radius => r - (y as f32 + 0.5),
+            y if y >= height - radius => (y - (height - radius)) as f32 + 0.5,
+            _ => continue,
+        };
+        for x in (0..radius).chain(width - radius..width) {
+            let dx = if x < radius {
+                r - (x as f32 + 0.5)
+            } else {
+                (x - (width - radius)) as f32 + 0.5
+            };
+            let coverage = (r - dx.hypot(dy) + 0.5).clamp(0.0, 1.0);
+            if coverage < 1.0 {
+                let pixel = image.get_pixel_mut(x, y);
+                pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+    /// 无损 WebP
+    Webp,
+}
+
+/// 图片编码选项
+#[derive(Clone, Copy)]
+pub struct EncodeOptions {
+    pub format: OutputFormat,
+    /// JPEG 质量，取值范围为 `1-100`
+    pub jpeg_quality: u8,
+    /// JPEG 不支持透明度，透明像素会与此颜色混合
+    pub background: [u8; 3],
+    /// 是否嵌入 sRGB 色彩配置文件
+    pub embed_srgb: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            format: OutputFormat::Png,
+            jpeg_quality: 80,
+            background: [255, 255, 255],
+            embed_srgb: false,
+        }
+    }
+}
+
+/// 将透明像素与背景颜色混合
+fn flatten_alpha(img: &RgbaImage, background: [u8; 3]) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img.get_pixel(x, y).0;
+        let alpha = a as f32 / 255.0;
+        let blend = |c: u8, bg: u8| (c as f32 * alpha + bg as f32 * (1.0 - alpha)).round() as u8;
+        Rgb([
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ])
+    })
+}
+
+/// 按选项为编码器设置 ICC 配置文件
+fn apply_color_profile<E: ImageEncoder>(encoder: &mut E, options: &EncodeOptions) -> Result<()> {
+    if options.embed_srgb {
+        encoder
+            .set_icc_profile(srgb_icc_profile().to_vec())
+            .map_err(|e| anyhow!("无法嵌入色彩配置文件: {}", e))?;
+    }
+    Ok(())
+}
+
+pub fn encode_image(img: &RgbaImage, options: &EncodeOptions) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let (width, height) = img.dimensions();
+    match options.format {
+        OutputFormat::Png => {
+            let mut encoder = PngEncoder::new(&mut buf);
+            apply_color_profile(&mut encoder, options)?;
+            encoder.write_image(img, width, height, ExtendedColorType::Rgba8)?;
+        }
+        OutputFormat::Webp => {
+            let mut encoder = WebPEncoder::new_lossless(&mut buf);
+            apply_color_profile(&mut encoder, options)?;
+            encoder.write_image(img, width, height, ExtendedColorType::Rgba8)?;
+        }
+        OutputFormat::Jpeg => {
+            let quality = options.jpeg_quality.clamp(1, 100);
+            let mut encoder = JpegEncoder::new_with_quality(&mut buf, quality);
+            apply_color_profile(&mut encoder, options)?;
+            encoder.encode_image(&flatten_alpha(img, options.background))?;
+        }
+    }
+    Ok(buf)
+}
+
+/// 按目标格式的编码大小逐步压缩图片，`max_size` 单位为 KB，为 0 时不压缩
+///
+/// JPEG 先逐步降低质量，降至下限后再缩小尺寸，均无法满足时返回最小的结果
+pub fn compress_image(
+    img: RgbaImage,
+    max_size: usize,
+    options: &EncodeOptions,
+) -> Result<(RgbaImage, Vec<u8>)> {
+    compress_with_limits(img, max_size, options, true)
+}
+
+/// `allow_resize` 为 `false` 时保持图片尺寸不变，仅降低 JPEG 质量
+fn compress_with_limits(
+    img: RgbaImage,
+    max_size: usize,
+    options: &EncodeOptions,
+    allow_resize: bool,
+) -> Result<(RgbaImage, Vec<u8>)> {
+    let mut options = *options;
+    let mut encoded = encode_image(&img, &options)?;
+    if max_size == 0 {
+        return Ok((img, encoded));
+    }
+
+    let target_size_bytes = max_size.saturating_mul(1024);
+    let mut candidate: Option<RgbaImage> = None;
+
+    for _ in 0..MAX_COMPRESS_ITERATIONS {
+        if encoded.len() <= target_size_bytes {
+            break;
+        }
+
+        if options.format == OutputFormat::Jpeg && options.jpeg_quality > MIN_JPEG_QUALITY {
+            options.jpeg_quality = options
+                .jpeg_quality
+                .saturating_sub(JPEG_QUALITY_STEP)
+                .max(MIN_JPEG_QUALITY);
+        } else {
+            let (width, height) = candidate.as_ref().unwrap_or(&img).dimensions();
+            if !allow_resize || width.min(height) <= MIN_COMPRESS_DIMENSION {
+                break;
+            }
+
+            let size_ratio = target_size_bytes as f32 / encoded.len() as f32;
+            let scale_factor = size_ratio.sqrt() * CONSERVATIVE_FACTOR;
+            let width = ((width as f32 * scale_factor) as u32).max(MIN_COMPRESS_DIMENSION);
+            let height = ((height as f32 * scale_factor) as u32).max(MIN_COMPRESS_DIMENSION);
+
+            // 始终从原图缩放，避免多次重采样损失细节
+            candidate = Some(imageops::resize(
+                &img,
+                width,
+                height,
+                imageops::FilterType::Lanczos3,
+            ));
+        }
+
+        encoded = encode_image(candidate.as_ref().unwrap_or(&img), &options)?;
+    }
+
+    Ok((candidate.unwrap_or(img), encoded))
+}
+
+/// 设置了 `output_size` 时压缩不再缩小图片，保证输出尺寸固定
+fn has_fixed_size(data_manager: &DataManager, character_id: &str) -> bool {
+    data_manager
+        .get_character(character_id)
+        .is_some_and(|config| config.output_size.is_some())
+}
+
+/// 按角色配置压缩已生成的图片，角色设置了 `output_size` 时不缩小尺寸
+pub fn compress_character_image(
+    data_manager: &DataManager,
+    character_id: &str,
+    img: RgbaImage,
+    max_size: usize,
+    options: &EncodeOptions,
+) -> Result<(RgbaImage, Vec<u8>)> {
+    compress_with_limits(
+        img,
+        max_size,
+        options,
+        !has_fixed_size(data_manager, character_id),
+    )
+}
+
+pub fn generate_image(
+    data_manager: &DataManager,
+    character_id: &str,
+    text: &str,
+    context: Option<&str>,
+    max_size: usize,
+    images: Option<&HashMap<String, Vec<String>>>,
+) -> Result<RgbaImage> {
+    let image = render_image(data_manager, character_id, text, context, images)?;
+    if max_size == 0 {
+        return Ok(image);
+    }
+
+    let (image, _) = compress_character_image(
+        data_manager,
+        character_id,
+        image,
+        max_size,
+        &EncodeOptions::default(),
+    )?;
+    Ok(image)
+}
+
+/// 生成图片并按指定格式编码，压缩时以目标格式的编码大小为准
+pub fn generate_encoded_image(
+    data_manager: &DataManager,
+    character_id: &str,
+    text: &str,
+    context: Option<&str>,
+    max_size: usize,
+    images: Option<&HashMap<String, Vec<String>>>,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
+    if options.format == OutputFormat::Jpeg
+        && data_manager
+            .get_character(character_id)
+            .is_some_and(|config| config.corner_radius > 0)
+    {
+        eprintln!("警告：JPEG 不支持透明度，圆角外的部分将以背景颜色填充");
+    }
+    let image = render_image(data_manager, character_id, text, context, images)?;
+    let (_, encoded) =
+        compress_character_image(data_manager, character_id, image, max_size, options)?;
+    Ok(encoded)
+}
+
+/// 按 z 值排序绘制的图层
+enum Layer<'a> {
+    Object(&'a ObjectConfig),
+    /// 文字区域及其中的文字
+    Textarea(&'a str, &'a TextAreaConfig),
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_object<T: Rng>(
+    image: &mut RgbaImage,
+    object: &ObjectConfig,
+    [offset_x, offset_y]: [i32; 2],
+    [frame_width, frame_height]: [u32; 2],
+    rng: &mut T,
+    data_manager: &DataManager,
+    character_config: &CharacterConfig,
+    images: Option<&HashMap<String, Vec<String>>>,
+    font: &FontVec,
+) -> Result<()> {
+    let [position_x, position_y] = object.position();
+    let position = [
+        position_x.resolve_position(frame_width),
+        position_y.resolve_position(frame_height),
+    ];
+    // 未设置 jitter 时不消耗随机数，保证相同种子下的结果不变
+    let [offset_x, offset_y] = match object.jitter() {
+        Some([jitter_x, jitter_y]) => [
+            offset_x + rng.random_range(-jitter_x.abs()..=jitter_x.abs()),
+            offset_y + rng.random_range(-jitter_y.abs()..=jitter_y.abs()),
+        ],
+        None => [offset_x, offset_y],
+    };
+    match object {
+        ObjectConfig::Image {
+            anchor,
+            path,
+            groups,
+            id,
+            opacity,
+            scale,
+            size,
+            rotation,
+            blend,
+            ..
+        } => {
+            let opacity = opacity.unwrap_or(1.0).clamp(0.0, 1.0);
+            if opacity == 0.0 {
+                return Ok(());
+            }
+            let paths = if let Some(img_map) = images
+                && let Some(img_id) = id
+                && let Some(img_paths) = img_map.get(img_id)
+            {
+                img_paths
+            } else if let Some(groups) = groups {
+                &choose_weighted(rng, groups, |g| g.weight)
+                    .ok_or_else(|| anyhow!("图片对象的 groups 中没有有效权重的分组"))?
+                    .path
+            } else {
+                path.as_ref().ok_or_else(|| {
+                    anyhow!("图片对象必须指定 path、groups 或者通过 id 在 images 参数中提供路径")
+                })?
+            };
+
+            let available_imgs = data_manager.get_images(character_config, paths);
+            if let Some(img) = data_manager.load_random_image(rng, &available_imgs) {
+                let mut img = Arc::unwrap_or_clone(img);
+                let target_size = size.or_else(|| {
+                    scale.map(|scale| {
+                        [img.width(), img.height()]
+                            .map(|side| (side as f32 * scale.max(0.0)).round() as u32)
+                    })
+                });
+                if let Some([width, height]) = target_size {
+                    if width == 0 || height == 0 {
+                        return Ok(());
+                    }
+                    if [width, height] != [img.width(), img.height()] {
+                        img = imageops::resize(&img, width, height, FilterType::Lanczos3);
+                    }
+                }
+                if let Some(rotation) = rotation.filter(|&rotation| rotation != 0.0) {
+                    img = rotate_object_image(&img, rotation);
+                }
+                if opacity < 1.0 {
+                    for pixel in img.pixels_mut() {
+                        pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+                    }
+                }
+                let [x, y] = anchor.to_top_left(
+                    [position[0] + offset_x, position[1] + offset_y],
+                    img.width(),
+                    img.height(),
+                );
+                match blend.unwrap_or_default() {
+                    BlendMode::Normal => imageops::overlay(image, &img, x as i64, y as i64),
+                    mode => blend_image(image, &img, x, y, mode),
+                }
+            }
+        }
+        ObjectConfig::Text {
+            text,
+            anchor,
+            font_color,
+            font_size,
+            ..
+        } => {
+            if !text.is_empty() {
+                let color = font_color.to_rgba(character_config.primary_color);
+                let (width, height) =
+                    text_size(get_scaled_font(font, *font_size).scale, font, text);
+                let [x, y] = anchor.to_top_left(
+                    [position[0] + offset_x, position[1] + offset_y],
+                    width,
+                    height,
+                );
+
+                draw_text_with_shadow(image, text, x, y, font, *font_size, color, (2, 2));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 按背景选择方式随机加载一张背景图片，均无法加载时使用后备背景
+fn load_background_image<R: Rng>(
+    data_manager: &DataManager,
+    character_config: &CharacterConfig,
+    text: &str,
+    rng: &mut R,
+) -> Result<RgbaImage> {
+    let backgrounds = data_manager
+        .get_backgrounds(character_config)
+        .unwrap_or_default();
+    let backgrounds = match &character_config.background_selection {
+        BackgroundSelection::Random => backgrounds,
+        BackgroundSelection::AspectRatio {
+            short_text,
+            long_text,
+        } => {
+            let text_len = text.chars().count();
+            if text_len <= *short_text {
+                filter_by_aspect_ratio(backgrounds, true)
+            } else if text_len >= *long_text {
+                filter_by_aspect_ratio(backgrounds, false)
+            } else {
+                backgrounds
+            }
+        }
+    };
+    let image = match data_manager.load_random_image(rng, &backgrounds) {
+        Some(image) => Arc::unwrap_or_clone(image),
+        None => {
+            // 背景均无法加载时以纯色画布代替
+            let fallback = character_config
+                .fallback_background
+                .as_ref()
+                .ok_or_else(|| {
+                    if backgrounds.is_empty() {
+                        anyhow!("角色 '{}' 没有可用的背景图片", character_config.id)
+                    } else {
+                        anyhow!("无法加载角色 '{}' 的背景图片", character_config.id)
+                    }
+                })?;
+            if !character_config.backgrounds.is_empty() {
+                eprintln!(
+                    "角色 '{}' 的背景图片加载失败，使用后备背景",
+                    character_config.id
+                );
+            }
+            background_canvas(fallback, character_config.primary_color)
+        }
+    };
+    Ok(image)
+}
+
+fn render_image(
+    data_manager: &DataManager,
+    character_id: &str,
+    text: &str,
+    context: Option<&str>,
+    images: Option<&HashMap<String, Vec<String>>>,
+) -> Result<RgbaImage> {
+    let character_config = data_manager
+        .get_character(character_id)
+        .ok_or_else(|| anyhow!("角色 '{}' 不存在", character_id))?;
+
+    let mut rng = rand::rng();
+
+    // 设置了生成背景时不加载背景图片
+    let mut image = match &character_config.generated_background {
+        Some(generated) => background_canvas(generated, character_config.primary_color),
+        None => load_background_image(data_manager, character_config, text, &mut rng)?,
+    };
+
+    // 主题颜色取自所选背景时，以替换了主题颜色的配置副本继续绘制
+    let adapted_config;
+    let character_config = if character_config.auto_primary_color {
+        adapted_config = CharacterConfig {
+            primary_color: dominant_color(&image).unwrap_or(BLACK),
+            ..character_config.clone()
+        };
+        &adapted_config
+    } else {
+        character_config
+    };
+
+    // 以背景可见内容的包围盒作为坐标系，百分比与负数坐标均相对于包围盒换算
+    let full_frame = ([0, 0], [image.width(), image.height()]);
+    let ([offset_x, offset_y], frame_size) = if character_config.anchor_to_content {
+        content_bounds(&image).unwrap_or(full_frame)
+    } else {
+        full_frame
+    };
+
+    let font = data_manager
+        .load_font(character_config)
+        .ok_or_else(|| anyhow!("无法加载角色 '{}' 的字体文件", character_id))?;
+    let emoji_font = data_manager.load_emoji_font(character_config);
+    let text_fonts = TextFonts::new(&font, emoji_font.as_deref());
+
+    // 引用的上文仅在角色配置了上文区域时绘制
+    let context_textarea = context
+        .filter(|context| !context.is_empty())
+        .zip(character_config.context_textarea.as_ref());
+    // 不满足绘制条件的物件直接跳过；物件与文字区域按 z 值从小到大绘制，z 值相同时按物件、上文、文字的顺序，物件之间保持配置中的顺序
+    let mut layers: Vec<Layer> = character_config
+        .objects
+        .iter()
+        .filter(|object| object.is_shown(text))
+        .map(Layer::Object)
+        .chain(context_textarea.map(|(context, config)| Layer::Textarea(context, config)))
+        .chain(std::iter::once(Layer::Textarea(
+            text,
+            &character_config.textarea,
+        )))
+        .collect();
+    layers.sort_by_key(|layer| match layer {
+        Layer::Object(object) => object.z(),
+        Layer::Textarea(_, config) => config.z,
+    });
+
+    for layer in layers {
+        match layer {
+            Layer::Object(object) => draw_object(
+                &mut image,
+                object,
+                [offset_x, offset_y],
+                frame_size,
+                &mut rng,
+                data_manager,
+                character_config,
+                images,
+                &font,
+            )?,
+            Layer::Textarea(text, config) => {
+                // 百分比按背景图片(标题栏与输出缩放之前)或其可见内容的尺寸换算
+                let mut textarea = config.resolve(frame_size);
+                let [x, y] = textarea.pixel_position();
+                textarea.position = [x + offset_x, y + offset_y].map(Length::Pixels);
+
+                draw_textarea(
+                    &mut image,
+                    text,
+                    text_fonts,
+                    &textarea,
+                    character_config.primary_color,
+                );
+            }
+        }
+    }
+
+    if let Some(header) = &character_config.header {
+        image = draw_header(
+            &mut rng,
+            image,
+            data_manager,
+            character_config,
+            header,
+            &font,
+        );
+    }
+
+    if let Some(output_size) = character_config.output_size {
+        image = fit_to_output(image, output_size, character_config.output_fit);
+    }
+
+    if let Some(border) = &character_config.border
+        && border.width > 0
+    {
+        // 指定了输出尺寸时总是内嵌，保持输出尺寸不变
+        image = draw_border(
+            image,
+            border,
+            border.inset || character_config.output_size.is_some(),
+            character_config.corner_radius,
+            character_config.primary_color,
+        );
+    }
+
+    if character_config.corner_radius > 0 {
+        round_corners(&mut image, character_config.corner_radius);
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::textarea::tests::{test_font, textarea};
+
+    const LINES: &str = "短\n很长很长的一行";
+
+    /// 以固定字号排版并放置文字，返回两行文字各自的起始横坐标与文本块宽度
+    fn line_starts(config: &TextAreaConfig) -> (i32, i32, u32) {
+        let fonts = TextFonts::new(test_font(), None);
+        let constraints = FitConstraints::from_config(config);
+        let prepared = prepare_textarea(LINES, fonts, config, &constraints);
+        let runs = place_horizontal(
+            &prepared,
+            config,
+            usable_region(config),
+            &fonts.scaled(prepared.font_size, config),
+        );
+        let start = |first: char| {
+            runs.iter()
+                .filter(|run| run.text.starts_with(first))
+                .map(|run| run.rect.0)
+                .min()
+                .unwrap()
+        };
+        (start('短'), start('很'), prepared.block_width)
+    }
+
+    fn aligned(align: &str, block_align: Option<&str>) -> TextAreaConfig {
+        let mut extra = json!({ "min_font_size": 20, "max_font_size": 20, "align": align });
+        if let Some(block_align) = block_align {
+            extra["block_align"] = json!(block_align);
+        }
+        textarea([400, 200], extra)
+    }
+
+    #[test]
+    fn lines_center_in_region_without_block_align() {
+        let (short, long, block_width) = line_starts(&aligned("center", None));
+        assert_eq!(long, (400 - block_width as i32) / 2);
+        assert!(short > long);
+    }
+
+    #[test]
+    fn block_align_left_centers_lines_within_block() {
+        let (short, long, block_width) = line_starts(&aligned("center", Some("left")));
+        assert_eq!(long, 0);
+        assert!(short > 0 && short < block_width as i32 / 2);
+    }
+
+    #[test]
+    fn block_align_right_keeps_lines_left_aligned_within_block() {
+        let (short, long, block_width) = line_starts(&aligned("left", Some("right")));
+        assert_eq!(long, 400 - block_width as i32);
+        assert_eq!(short, long);
+    }
+
+    #[test]
+    fn block_align_center_matches_line_center_for_widest_line() {
+        let (_, long, block_width) = line_starts(&aligned("left", Some("center")));
+        assert_eq!(long, (400 - block_width as i32) / 2);
+    }
+
+    /// 图片中不透明像素的包围盒，返回左上角与右下角(不含)坐标
+    fn ink_bounds(image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+        ink_bounds_above(image, 0)
+    }
+
+    /// 透明度高于阈值的像素的包围盒，忽略缩放插值产生的淡边
+    fn ink_bounds_above(image: &RgbaImage, threshold: u8) -> Option<(u32, u32, u32, u32)> {
+        image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] > threshold)
+            .fold(None, |bounds, (x, y, _)| {
+                let (x1, y1, x2, y2) = bounds.unwrap_or((x, y, x + 1, y + 1));
+                Some((x1.min(x), y1.min(y), x2.max(x + 1), y2.max(y + 1)))
+            })
+    }
+
+    #[test]
+    fn stroke_and_shadow_stay_inside_region() {
+        let config = textarea(
+            [200, 100],
+            json!({
+                "font_color": "white",
+                "stroke": { "color": "black", "width": 8 },
+                "shadow_offset": [6, 6]
+            }),
+        );
+        let mut local = config.clone();
+        local.position = [Length::Pixels(50), Length::Pixels(50)];
+        let mut image = RgbaImage::new(300, 200);
+        draw_textarea(
+            &mut image,
+            "描边文字测试",
+            TextFonts::new(test_font(), None),
+            &local,
+            BLACK,
+        );
+
+        let (x1, y1, x2, y2) = ink_bounds(&image).unwrap();
+        assert!(x1 >= 50 && y1 >= 50, "{:?}", (x1, y1));
+        assert!(x2 <= 250 && y2 <= 150, "{:?}", (x2, y2));
+    }
+
+    fn box_fill(value: serde_json::Value) -> BoxFill {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn box_fill_covers_rect_with_opacity() {
+        let mut image = RgbaImage::new(40, 30);
+        let fill = box_fill(json!({ "color": [255, 0, 0], "opacity": 0.5 }));
+        draw_box_fill(&mut image, (10, 5, 30, 25), &fill, BLACK);
+
+        assert_eq!(ink_bounds(&image), Some((10, 5, 30, 25)));
+        let pixel = image.get_pixel(20, 15);
+        assert_eq!(pixel[0], 255);
+        assert!((pixel[3] as i32 - 128).abs() <= 1, "{:?}", pixel);
+    }
+
+    #[test]
+    fn box_fill_rounds_corners() {
+        let mut image = RgbaImage::new(40, 40);
+        let fill = box_fill(json!({ "color": [0, 0, 255], "corner_radius": 10 }));
+        draw_box_fill(&mut image, (0, 0, 40, 40), &fill, BLACK);
+
+        for (x, y) in [(0, 0), (39, 0), (0, 39), (39, 39)] {
+            assert_eq!(image.get_pixel(x, y)[3], 0, "corner ({x}, {y})");
+        }
+        assert_eq!(image.get_pixel(20, 0)[3], 255);
+        assert_eq!(image.get_pixel(0, 20)[3], 255);
+        assert_eq!(image.get_pixel(20, 20)[3], 255);
+    }
+
+    #[test]
+    fn box_fill_is_clipped_to_image() {
+        let mut image = RgbaImage::new(20, 20);
+        let fill = box_fill(json!({ "color": [0, 255, 0] }));
+        draw_box_fill(&mut image, (-10, -10, 10, 50), &fill, BLACK);
+        assert_eq!(ink_bounds(&image), Some((0, 0, 10, 20)));
+
+        // 完全在图片外时不绘制
+        let mut image = RgbaImage::new(20, 20);
+        draw_box_fill(&mut image, (30, 30, 40, 40), &fill, BLACK);
+        assert_eq!(ink_bounds(&image), None);
+    }
+
+    #[test]
+    fn box_fill_padding_is_limited_to_region() {
+        let mut config = textarea(
+            [200, 100],
+            json!({ "box_fill": { "color": [0, 0, 0], "padding": 1000 } }),
+        );
+        config.position = [Length::Pixels(50), Length::Pixels(50)];
+        let mut image = RgbaImage::new(300, 200);
+        draw_textarea(
+            &mut image,
+            "底板",
+            TextFonts::new(test_font(), None),
+            &config,
+            BLACK,
+        );
+        assert_eq!(ink_bounds(&image), Some((50, 50, 250, 150)));
+    }
+
+    /// 以给定的超采样倍数绘制固定字号的文字
+    fn render_supersampled(factor: u32) -> RgbaImage {
+        let mut config = textarea(
+            [200, 100],
+            json!({
+                "max_font_size": 40,
+                "min_font_size": 40,
+                "stroke": { "color": "black", "width": 2 },
+                "supersample": factor
+            }),
+        );
+        config.position = [Length::Pixels(50), Length::Pixels(50)];
+        let mut image = RgbaImage::new(300, 200);
+        draw_textarea(
+            &mut image,
+            "超采样",
+            TextFonts::new(test_font(), None),
+            &config,
+            BLACK,
+        );
+        image
+    }
+
+    #[test]
+    fn supersample_keeps_size_and_placement() {
+        let plain = render_supersampled(1);
+        let sampled = render_supersampled(2);
+        assert_eq!(sampled.dimensions(), plain.dimensions());
+
+        let (x1, y1, x2, y2) = ink_bounds_above(&plain, 127).unwrap();
+        let (sx1, sy1, sx2, sy2) = ink_bounds_above(&sampled, 127).unwrap();
+        for (a, b) in [(x1, sx1), (y1, sy1), (x2, sx2), (y2, sy2)] {
+            assert!(
+                a.abs_diff(b) <= 2,
+                "{:?} != {:?}",
+                (x1, y1, x2, y2),
+                (sx1, sy1, sx2, sy2)
+            );
+        }
+
+        // 超采样后仍完全位于文字区域内
+        let (x1, y1, x2, y2) = ink_bounds(&sampled).unwrap();
+        assert!(x1 >= 50 && y1 >= 50 && x2 <= 250 && y2 <= 150);
+
+        // 笔画粗细相近，总覆盖量相差不大
+        let coverage = |image: &RgbaImage| image.pixels().map(|p| p[3] as u64).sum::<u64>();
+        let (plain, sampled) = (coverage(&plain), coverage(&sampled));
+        assert!(plain.abs_diff(sampled) * 10 < plain, "{plain} vs {sampled}");
+    }
+
+    /// 在临时目录中写入资源配置并加载，角色只有后备背景而没有背景图片
+    fn fallback_only_data(name: &str, fallback: serde_json::Value) -> DataManager {
+        temp_data(name, json!({}), json!({ "fallback_background": fallback }))
+    }
+
+    /// 在临时目录中写入资源配置并加载，`character` 中的字段补充到角色 `a` 的基本配置中
+    fn temp_data(
+        name: &str,
+        template: serde_json::Value,
+        character: serde_json::Value,
+    ) -> DataManager {
+        let dir = std::env::temp_dir().join(format!("imagebox-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut fields = json!({
+            "name": "A",
+            "font": concat!(env!("CARGO_MANIFEST_DIR"), "/../../data/fonts/font.ttf"),
+            "textarea": { "position": [60, 40], "size": [60, 40], "font_color": "white" }
+        });
+        fields
+            .as_object_mut()
+            .unwrap()
+            .extend(character.as_object().unwrap().clone());
+        let config = json!({ "template": template, "characters": { "a": fields } });
+        let config_path = dir.join("data.json");
+        std::fs::write(&config_path, config.to_string()).unwrap();
+        let data_manager = DataManager::new(&config_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        data_manager
+    }
+
+    #[test]
+    fn fallback_background_renders_without_backgrounds() {
+        let data_manager = fallback_only_data(
+            "fallback",
+            json!({ "size": [120, 80], "color": [10, 20, 30] }),
+        );
+        let image = generate_image(&data_manager, "a", "文字", None, 0, None).unwrap();
+        assert_eq!(image.dimensions(), (120, 80));
+        // 文字区域以外保持后备背景的颜色，区域内绘制了文字
+        assert_eq!(*image.get_pixel(10, 10), Rgba([10, 20, 30, 255]));
+        assert!(
+            image
+                .enumerate_pixels()
+                .any(|(x, y, pixel)| x >= 60 && y >= 40 && pixel[0] > 128)
+        );
+    }
+
+    #[test]
+    fn fallback_background_draws_gradient() {
+        let data_manager = fallback_only_data(
+            "gradient",
+            json!({
+                "size": [120, 80],
+                "color": [0, 0, 0],
+                "end_color": [200, 200, 200],
+                "direction": "horizontal"
+            }),
+        );
+        let image = generate_image(&data_manager, "a", "", None, 0, None).unwrap();
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(119, 0), Rgba([200, 200, 200, 255]));
+        assert_eq!(image.get_pixel(0, 0), image.get_pixel(0, 79));
+    }
+
+    /// 在白色背景上以给定的旋转角度与超采样倍数绘制白色文字，
+    /// 白色描边盖住偏移为 0 时文字下方的黑色阴影
+    fn render_white_on_white(rotation: f32, factor: u32) -> RgbaImage {
+        let mut config = textarea(
+            [200, 100],
+            json!({
+                "font_color": "white",
+                "stroke": { "color": "white", "width": 2 },
+                "max_font_size": 40,
+                "rotation": rotation,
+                "supersample": factor
+            }),
+        );
+        config.position = [Length::Pixels(50), Length::Pixels(50)];
+        let mut image = RgbaImage::from_pixel(300, 200, WHITE);
+        draw_textarea(
+            &mut image,
+            "旋转文字",
+            TextFonts::new(test_font(), None),
+            &config,
+            BLACK,
+        );
+        image
+    }
+
+    /// 图片中最暗的颜色通道值。正确合成时白色文字在白色背景上只因多次混合的舍入略微偏暗，
+    /// 重复预乘透明度时边缘会明显变暗
+    fn darkest_channel(image: &RgbaImage) -> u8 {
+        image
+            .pixels()
+            .flat_map(|p| p.0[..3].to_vec())
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn rotated_text_edges_are_not_darkened() {
+        let upright = darkest_channel(&render_white_on_white(0.0, 1));
+        let rotated = darkest_channel(&render_white_on_white(15.0, 1));
+        assert!(rotated >= 240, "{rotated}");
+        assert!(rotated + 3 >= upright, "{rotated} vs {upright}");
+    }
+
+    #[test]
+    fn supersampled_text_edges_are_not_darkened() {
+        for rotation in [0.0, 15.0] {
+            let plain = darkest_channel(&render_white_on_white(rotation, 1));
+            let sampled = darkest_channel(&render_white_on_white(rotation, 2));
+            // 缩小时边缘的多次混合被平均，超采样后的边缘应不比直接绘制时更暗
+            assert!(sampled >= 240, "rotation {rotation}: {sampled}");
+            assert!(
+                sampled >= plain,
+                "rotation {rotation}: {sampled} vs {plain}"
+            );
+        }
+    }
+
+    #[test]
+    fn generated_background_takes_precedence_over_backgrounds() {
+        let data_manager = temp_data(
+            "generated",
+            json!({ "backgrounds": ["*.png"] }),
+            json!({
+                "generated_background": {
+                    "size": [100, 60],
+                    "color": [0, 0, 255],
+                    "end_color": [255, 0, 0]
+                }
+            }),
+        );
+        let image = generate_image(&data_manager, "a", "", None, 0, None).unwrap();
+        assert_eq!(image.dimensions(), (100, 60));
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+        assert_eq!(*image.get_pixel(0, 59), Rgba([255, 0, 0, 255]));
+    }
+
+    /// 背景为 200x100 的透明图片，可见内容位于 (50, 20) 处、大小为 100x60，
+    /// 以可见内容为坐标系绘制填满文字区域的红色底板
+    fn render_anchored(
+        name: &str,
+        position: serde_json::Value,
+        size: serde_json::Value,
+    ) -> RgbaImage {
+        let dir = std::env::temp_dir().join(format!("imagebox-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("backgrounds")).unwrap();
+        let mut background = RgbaImage::new(200, 100);
+        for (x, y, pixel) in background.enumerate_pixels_mut() {
+            if (50..150).contains(&x) && (20..80).contains(&y) {
+                *pixel = Rgba([128, 128, 128, 255]);
+            }
+        }
+        background.save(dir.join("backgrounds/bg.png")).unwrap();
+        let config = json!({
+            "characters": {
+                "a": {
+                    "name": "A",
+                    "backgrounds": ["*.png"],
+                    "font": concat!(env!("CARGO_MANIFEST_DIR"), "/../../data/fonts/font.ttf"),
+                    "anchor_to_content": true,
+                    "textarea": {
+                        "position": position,
+                        "size": size,
+                        "font_color": "white",
+                        "box_fill": { "color": [255, 0, 0], "padding": 1000 }
+                    }
+                }
+            }
+        });
+        let config_path = dir.join("data.json");
+        std::fs::write(&config_path, config.to_string()).unwrap();
+        let data_manager = DataManager::new(&config_path).unwrap();
+        let image = generate_image(&data_manager, "a", "字", None, 0, None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        image
+    }
+
+    /// 红色底板的包围盒
+    fn red_bounds(image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+        let mut red = image.clone();
+        for pixel in red.pixels_mut() {
+            if pixel.0 != [255, 0, 0, 255] {
+                *pixel = Rgba([0, 0, 0, 0]);
+            }
+        }
+        ink_bounds(&red)
+    }
+
+    #[test]
+    fn anchor_to_content_resolves_percentages_against_content() {
+        let image = render_anchored(
+            "anchor-percent",
+            json!(["50%", "50%"]),
+            json!(["50%", "50%"]),
+        );
+        assert_eq!(image.dimensions(), (200, 100));
+        assert_eq!(red_bounds(&image), Some((100, 50, 150, 80)));
+    }
+
+    #[test]
+    fn anchor_to_content_resolves_negative_positions_from_content_edge() {
+        let image = render_anchored("anchor-negative", json!([-40, -30]), json!([30, 20]));
+        assert_eq!(red_bounds(&image), Some((110, 50, 140, 70)));
+    }
 }
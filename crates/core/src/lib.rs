@@ -1,11 +1,20 @@
+mod color_profile;
 mod data;
 mod data_manager;
 mod image_generator;
+mod named_colors;
 mod resource_loader;
 mod textarea;
 
 pub use data::{
-    CharacterConfig, ColorInput, HorizontalAlign, ObjectConfig, TextAreaConfig, VerticalAlign,
+    Anchor, BackgroundSelection, BlendMode, BorderConfig, BoxFill, CharacterConfig, ColorInput,
+    FallbackBackground, GradientDirection, HeaderConfig, HighlightInput, HorizontalAlign,
+    ImageGroup, Length, ObjectCondition, ObjectConfig, OutputFit, TextAreaConfig, TextDirection,
+    TextOverflow, TextStroke, VerticalAlign, WritingMode,
 };
 pub use data_manager::DataManager;
-pub use image_generator::generate_image;
+pub use image_generator::{
+    EncodeOptions, OutputFormat, compress_character_image, compress_image, encode_image,
+    generate_encoded_image, generate_image,
+};
+pub use textarea::FitReport;
@@ -1,10 +1,14 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use ab_glyph::FontVec;
 use image::{ImageReader, RgbaImage};
 use rand::Rng;
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
 
 pub fn load_image(path: &Path) -> Option<RgbaImage> {
     match ImageReader::open(path) {
@@ -16,14 +20,41 @@ pub fn load_image(path: &Path) -> Option<RgbaImage> {
     }
 }
 
-pub fn load_random_image<T: Rng>(rng: &mut T, paths: &[PathBuf]) -> Option<RgbaImage> {
+/// 按宽高比筛选图片，仅读取文件头获取尺寸，没有符合条件的图片时返回原列表
+pub fn filter_by_aspect_ratio(paths: Vec<PathBuf>, prefer_wide: bool) -> Vec<PathBuf> {
+    let preferred = paths
+        .iter()
+        .filter(|path| {
+            image::image_dimensions(path).is_ok_and(|(width, height)| {
+                if prefer_wide {
+                    width > height
+                } else {
+                    height > width
+                }
+            })
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if preferred.is_empty() {
+        paths
+    } else {
+        preferred
+    }
+}
+
+pub fn load_random_image<T: Rng>(
+    rng: &mut T,
+    paths: &[PathBuf],
+    cache: &ImageCache,
+) -> Option<Arc<RgbaImage>> {
     if paths.is_empty() {
         return None;
     }
 
     for _ in 0..3 {
         let idx = rng.random_range(0..paths.len());
-        if let Some(img) = load_image(&paths[idx]) {
+        if let Some(img) = cache.get(&paths[idx]) {
             return Some(img);
         }
     }
@@ -31,6 +62,15 @@ pub fn load_random_image<T: Rng>(rng: &mut T, paths: &[PathBuf]) -> Option<RgbaI
     None
 }
 
+/// 按权重随机选择一项，权重均无效时返回 `None`
+pub fn choose_weighted<'a, T, R: Rng, F>(rng: &mut R, items: &'a [T], weight: F) -> Option<&'a T>
+where
+    F: Fn(&T) -> f32,
+{
+    let dist = WeightedIndex::new(items.iter().map(|item| weight(item).max(0.0))).ok()?;
+    items.get(dist.sample(rng))
+}
+
 pub fn load_font(font_path: &Path) -> Option<Arc<FontVec>> {
     match fs::read(font_path) {
         Ok(font_data) => match FontVec::try_from_vec(font_data) {
@@ -40,3 +80,242 @@ pub fn load_font(font_path: &Path) -> Option<Arc<FontVec>> {
         Err(_) => None,
     }
 }
+
+/// 内置的后备字体，仅在启用 `fallback-font` 特性时可用
+#[cfg(feature = "fallback-font")]
+pub fn fallback_font() -> Option<Arc<FontVec>> {
+    use std::sync::OnceLock;
+
+    static FALLBACK_FONT: OnceLock<Option<Arc<FontVec>>> = OnceLock::new();
+    FALLBACK_FONT
+        .get_or_init(|| {
+            // 与默认资源包共用同一个字体文件
+            let font_data = include_bytes!("../../../data/fonts/font.ttf");
+            FontVec::try_from_vec(font_data.to_vec()).ok().map(Arc::new)
+        })
+        .clone()
+}
+
+#[cfg(not(feature = "fallback-font"))]
+pub fn fallback_font() -> Option<Arc<FontVec>> {
+    None
+}
+
+/// 按路径缓存已加载的字体，文件修改时间变化时重新加载
+#[derive(Default)]
+pub struct FontCache {
+    fonts: Mutex<HashMap<PathBuf, (SystemTime, Arc<FontVec>)>>,
+}
+
+impl FontCache {
+    pub fn get(&self, font_path: &Path) -> Option<Arc<FontVec>> {
+        let modified = fs::metadata(font_path).and_then(|m| m.modified());
+
+        if let Ok(modified) = modified
+            && let Some((cached_modified, font)) = self.fonts.lock().unwrap().get(font_path)
+            && *cached_modified == modified
+        {
+            return Some(font.clone());
+        }
+
+        // 加载时不持有锁，避免阻塞其他线程读取已缓存的字体
+        let font = load_font(font_path);
+
+        // 直接覆盖或移除旧条目，旧字体在不再被引用后释放
+        let mut fonts = self.fonts.lock().unwrap();
+        match (modified, font) {
+            (Ok(modified), Some(font)) => {
+                fonts.insert(font_path.to_path_buf(), (modified, font.clone()));
+                Some(font)
+            }
+            (_, font) => {
+                fonts.remove(font_path);
+                font
+            }
+        }
+    }
+}
+
+/// 图片缓存最多保留的图片数量，超出时移除最久未使用的图片
+const IMAGE_CACHE_CAPACITY: usize = 32;
+
+struct CachedImage {
+    modified: SystemTime,
+    last_used: u64,
+    image: Arc<RgbaImage>,
+}
+
+#[derive(Default)]
+struct ImageCacheEntries {
+    images: HashMap<PathBuf, CachedImage>,
+    clock: u64,
+}
+
+/// 按路径缓存已解码的图片，文件修改时间变化时重新解码
+#[derive(Default)]
+pub struct ImageCache {
+    entries: Mutex<ImageCacheEntries>,
+}
+
+impl ImageCache {
+    pub fn get(&self, path: &Path) -> Option<Arc<RgbaImage>> {
+        let modified = fs::metadata(path).and_then(|m| m.modified());
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let clock = entries.clock;
+
+            if let Ok(modified) = modified
+                && let Some(cached) = entries.images.get_mut(path)
+                && cached.modified == modified
+            {
+                cached.last_used = clock;
+                return Some(cached.image.clone());
+            }
+        }
+
+        // 解码时不持有锁，避免一张图片的解码阻塞其他线程
+        let image = load_image(path).map(Arc::new);
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        match (modified, image) {
+            (Ok(modified), Some(image)) => {
+                if !entries.images.contains_key(path)
+                    && entries.images.len() >= IMAGE_CACHE_CAPACITY
+                    && let Some(oldest) = entries
+                        .images
+                        .iter()
+                        .min_by_key(|(_, cached)| cached.last_used)
+                        .map(|(path, _)| path.clone())
+                {
+                    entries.images.remove(&oldest);
+                }
+                entries.images.insert(
+                    path.to_path_buf(),
+                    CachedImage {
+                        modified,
+                        last_used: clock,
+                        image: image.clone(),
+                    },
+                );
+                Some(image)
+            }
+            (_, image) => {
+                entries.images.remove(path);
+                image
+            }
+        }
+    }
+}
+
+/// 按匹配模式缓存 glob 的结果，模式所在目录的修改时间变化时重新匹配，
+/// 目录部分含通配符的模式无法判断是否变化，每次都重新匹配
+#[derive(Default)]
+pub struct GlobCache {
+    paths: Mutex<HashMap<PathBuf, (SystemTime, Vec<PathBuf>)>>,
+}
+
+impl GlobCache {
+    /// 返回 `dir` 下匹配 `pattern` 的文件
+    pub fn get(&self, dir: &Path, pattern: &str) -> Vec<PathBuf> {
+        let full_pattern = dir.join(pattern);
+        let modified = full_pattern
+            .parent()
+            .filter(|parent| !parent.to_string_lossy().contains(['*', '?', '[']))
+            .and_then(|parent| fs::metadata(parent).and_then(|m| m.modified()).ok());
+        let Some(modified) = modified else {
+            return collect_files(&full_pattern);
+        };
+
+        if let Some((cached_modified, paths)) = self.paths.lock().unwrap().get(&full_pattern)
+            && *cached_modified == modified
+        {
+            return paths.clone();
+        }
+
+        let paths = collect_files(&full_pattern);
+        self.paths
+            .lock()
+            .unwrap()
+            .insert(full_pattern, (modified, paths.clone()));
+        paths
+    }
+}
+
+fn collect_files(full_pattern: &Path) -> Vec<PathBuf> {
+    let mut path_list = Vec::new();
+
+    if let Ok(paths) = glob::glob(&full_pattern.to_string_lossy()) {
+        for entry in paths.flatten() {
+            if entry.is_file() {
+                path_list.push(entry);
+            }
+        }
+    }
+
+    path_list
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use super::*;
+
+    const FONT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../data/fonts/font.ttf");
+
+    #[test]
+    fn font_cache_reloads_modified_font() {
+        let path = std::env::temp_dir().join(format!("imagebox-font-{}.ttf", std::process::id()));
+        fs::copy(FONT_PATH, &path).unwrap();
+        let cache = FontCache::default();
+
+        let first = cache.get(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.get(&path).unwrap()));
+
+        // 修改时间变化后重新加载并替换旧条目
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+        drop(file);
+        let second = cache.get(&path).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert!(Arc::ptr_eq(&second, &cache.get(&path).unwrap()));
+        assert_eq!(cache.fonts.lock().unwrap().len(), 1);
+
+        // 文件删除后移除条目
+        fs::remove_file(&path).unwrap();
+        assert!(cache.get(&path).is_none());
+        assert!(cache.fonts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn glob_cache_rescans_when_directory_changes() {
+        let dir = std::env::temp_dir().join(format!("imagebox-glob-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.png"), b"").unwrap();
+        let cache = GlobCache::default();
+        assert_eq!(cache.get(&dir, "*.png"), [dir.join("a.png")]);
+
+        // 目录修改时间不变时沿用缓存的结果
+        let modified = fs::metadata(&dir).unwrap().modified().unwrap();
+        fs::write(dir.join("b.png"), b"").unwrap();
+        File::open(&dir).unwrap().set_modified(modified).unwrap();
+        assert_eq!(cache.get(&dir, "*.png").len(), 1);
+
+        File::open(&dir)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+        let mut paths = cache.get(&dir, "*.png");
+        paths.sort();
+        assert_eq!(paths, [dir.join("a.png"), dir.join("b.png")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(cache.get(&dir, "*.png").is_empty());
+    }
+}
@@ -1,50 +1,314 @@
 use ab_glyph::{Font, FontVec, PxScale, PxScaleFont, ScaleFont};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::data::{TextAreaConfig, TextDirection, TextOverflow, WritingMode};
+
+/// 模拟斜体的错切系数，即每向上一个像素向右偏移的距离
+pub const ITALIC_SHEAR: f32 = 0.2;
+/// 注音文字相对于所注文字的字号比例
+pub const RUBY_SCALE: f32 = 0.5;
+
+/// 相对于文字区域字号的大小
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelativeSize {
+    Small,
+    Large,
+}
+
+impl RelativeSize {
+    const ALL: [RelativeSize; 2] = [RelativeSize::Small, RelativeSize::Large];
+
+    pub fn factor(self) -> f32 {
+        match self {
+            RelativeSize::Small => 0.75,
+            RelativeSize::Large => 1.25,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RelativeSize::Small => "small",
+            RelativeSize::Large => "large",
+        }
+    }
+
+    fn opening_tag(self) -> String {
+        format!("{{{}}}", self.name())
+    }
+
+    fn closing_tag(self) -> String {
+        format!("{{/{}}}", self.name())
+    }
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct TextSegment {
     pub text: String,
-    pub is_highlighted: bool,
+    /// 高亮级别，即高亮标记的序号，默认“【】”为 0，“[]”为 1
+    pub highlight: Option<usize>,
+    pub underline: bool,
+    pub strikethrough: bool,
+    /// 模拟粗体，通过错位重复绘制实现
+    pub bold: bool,
+    /// 模拟斜体，通过错切变换实现
+    pub italic: bool,
+    /// 相对字号，未设置时使用文字区域的字号
+    pub size: Option<RelativeSize>,
+    /// 标注在文字上方的注音，带注音的文本段排版时不会被拆分
+    pub ruby: Option<String>,
+}
+
+impl TextSegment {
+    fn with_text(&self, text: String) -> Self {
+        TextSegment {
+            text,
+            highlight: self.highlight,
+            underline: self.underline,
+            strikethrough: self.strikethrough,
+            bold: self.bold,
+            italic: self.italic,
+            size: self.size,
+            ruby: self.ruby.clone(),
+        }
+    }
+
+    /// 带注音的文本段各自独立，不与相邻文本段合并
+    fn same_style(&self, other: &TextSegment) -> bool {
+        self.ruby.is_none()
+            && other.ruby.is_none()
+            && self.highlight == other.highlight
+            && self.underline == other.underline
+            && self.strikethrough == other.strikethrough
+            && self.bold == other.bold
+            && self.italic == other.italic
+            && self.size == other.size
+    }
+
+    /// 关闭当前样式所需追加的标记
+    fn closing_markers(&self) -> String {
+        let mut markers: String = [
+            (self.underline, "_"),
+            (self.strikethrough, "~"),
+            (self.bold, "**"),
+            (self.italic, "*"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, marker)| enabled.then_some(marker))
+        .collect();
+        if let Some(size) = self.size {
+            markers.push_str(&size.closing_tag());
+        }
+        markers
+    }
+}
+
+fn push_segment(segments: &mut Vec<TextSegment>, current: &mut TextSegment) {
+    if !current.text.is_empty() {
+        let text = std::mem::take(&mut current.text);
+        segments.push(current.with_text(text));
+    }
+}
+
+/// 默认的高亮标记，“【】”为 0 级，“[]”为 1 级
+const DEFAULT_HIGHLIGHT_DELIMITERS: [(&str, &str); 2] = [("【", "】"), ("[", "]")];
+
+/// 文字区域使用的高亮标记，下标为对应的高亮级别
+fn highlight_delimiters(config: &TextAreaConfig) -> Vec<(&str, &str)> {
+    match &config.highlight_delimiters {
+        Some((open, close)) => vec![(open.as_str(), close.as_str())],
+        None => DEFAULT_HIGHLIGHT_DELIMITERS.to_vec(),
+    }
 }
 
-fn parse_highlighted_text(text: &str) -> Vec<TextSegment> {
+/// 除高亮标记外可以用反斜杠转义为普通字符的格式标记
+const ESCAPABLE_CHARS: [char; 7] = ['\\', '_', '~', '*', '{', '(', '（'];
+
+/// 将被转义的字符及其反斜杠替换为空字符，用于查找配对标记时跳过被转义的标记，
+/// 高亮标记以其首个字符转义
+fn mask_escaped(chars: &[char], delimiters: &[(&str, &str)]) -> Vec<char> {
+    let is_escapable = |c: char| {
+        ESCAPABLE_CHARS.contains(&c)
+            || delimiters
+                .iter()
+                .any(|(open, close)| open.starts_with(c) || close.starts_with(c))
+    };
+    let mut masked = chars.to_vec();
+    let mut index = 0;
+    while index + 1 < chars.len() {
+        if chars[index] == '\\' && is_escapable(chars[index + 1]) {
+            masked[index] = '\0';
+            masked[index + 1] = '\0';
+            index += 1;
+        }
+        index += 1;
+    }
+    masked
+}
+
+fn is_han_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{3005}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{20000}'..='\u{3FFFF}'
+    )
+}
+
+fn is_kana_char(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}')
+}
+
+fn starts_with_tag(chars: &[char], tag: &str) -> bool {
+    let len = tag.chars().count();
+    chars.len() >= len && chars[..len].iter().copied().eq(tag.chars())
+}
+
+fn contains_tag(chars: &[char], tag: &str) -> bool {
+    (0..chars.len()).any(|index| starts_with_tag(&chars[index..], tag))
+}
+
+/// 解析格式标记：高亮标记(默认为"【】"或"[]")内的文字高亮(保留标记)，成对的"_"、"~"、"**"、"*"之间的文字
+/// 添加下划线、删除线、粗体、斜体，"{small}...{/small}"与"{large}...{/large}"之间的文字
+/// 缩小或放大字号(移除标记)，汉字后紧跟括号括起的假名时作为注音标注在汉字上方(移除括号)，
+/// 标记前加反斜杠时按普通字符显示(移除反斜杠)
+fn parse_highlighted_text(text: &str, delimiters: &[(&str, &str)]) -> Vec<TextSegment> {
+    parse_markup(text, usize::MAX, delimiters).0
+}
+
+/// 解析前 `end` 个字符的格式标记，标记是否配对按全文判断，同时返回解析到该处时的样式
+fn parse_markup(
+    text: &str,
+    end: usize,
+    delimiters: &[(&str, &str)],
+) -> (Vec<TextSegment>, TextSegment) {
+    let chars: Vec<char> = text.chars().collect();
+    let masked = mask_escaped(&chars, delimiters);
+    let end = end.min(chars.len());
     let mut segments = Vec::new();
-    let mut current = String::new();
-    let mut in_highlight = false;
+    let mut current = TextSegment::default();
+
+    let mut index = 0;
+    while index < end {
+        let ch = chars[index];
+        // 被转义的标记原样输出，末尾单独的反斜杠按普通字符处理
+        if ch == '\\' && index + 1 < end && masked[index] == '\0' {
+            current.text.push(chars[index + 1]);
+            index += 2;
+            continue;
+        }
+        let rest = &masked[index + 1..];
+        // 没有与之配对的标记时按普通字符处理
+        let has_closing = || rest.contains(&ch);
+        let is_double_star = ch == '*' && index + 1 < end && rest.first() == Some(&'*');
+
+        // 高亮标记保留在文字中，高亮中遇到任一结束标记即结束，起止标记相同时交替开始与结束
+        let ahead = &masked[index..end];
+        if current.highlight.is_some()
+            && let Some(&(_, close)) = delimiters
+                .iter()
+                .find(|(_, close)| starts_with_tag(ahead, close))
+        {
+            current.text.push_str(close);
+            push_segment(&mut segments, &mut current);
+            current.highlight = None;
+            index += close.chars().count();
+            continue;
+        }
+        if let Some((level, &(open, _))) = delimiters
+            .iter()
+            .enumerate()
+            .find(|(_, (open, _))| starts_with_tag(ahead, open))
+        {
+            push_segment(&mut segments, &mut current);
+            current.highlight = Some(level);
+            current.text.push_str(open);
+            index += open.chars().count();
+            continue;
+        }
 
-    for ch in text.chars() {
-        if ch == '【' || ch == '[' {
-            if !current.is_empty() {
+        // 注音仅由假名组成，避免将普通的括号注释当作注音
+        if matches!(ch, '(' | '（') {
+            let closing = if ch == '(' { ')' } else { '）' };
+            let reading: Option<String> = chars[index + 1..end]
+                .iter()
+                .position(|&c| c == closing)
+                .map(|len| &chars[index + 1..index + 1 + len])
+                .filter(|reading| !reading.is_empty() && reading.iter().all(|&c| is_kana_char(c)))
+                .map(|reading| reading.iter().collect());
+            let base_len: usize = current
+                .text
+                .chars()
+                .rev()
+                .take_while(|&c| is_han_char(c))
+                .map(char::len_utf8)
+                .sum();
+            if let Some(reading) = reading
+                && base_len > 0
+            {
+                let base = current.text.split_off(current.text.len() - base_len);
+                push_segment(&mut segments, &mut current);
+                index += reading.chars().count() + 2;
                 segments.push(TextSegment {
-                    text: current.clone(),
-                    is_highlighted: in_highlight,
+                    ruby: Some(reading),
+                    ..current.with_text(base)
                 });
-                current.clear();
+                continue;
             }
-            in_highlight = true;
-            current.push(ch);
-        } else if ch == '】' || ch == ']' {
-            current.push(ch);
-            if in_highlight {
-                segments.push(TextSegment {
-                    text: current.clone(),
-                    is_highlighted: true,
-                });
-                current.clear();
-                in_highlight = false;
+        }
+
+        // 字号标记不可嵌套，未配对或嵌套的标记按普通字符处理
+        if ch == '{' {
+            let toggled = match current.size {
+                Some(size) => {
+                    let tag = size.closing_tag();
+                    starts_with_tag(ahead, &tag).then_some((None, tag))
+                }
+                None => RelativeSize::ALL.into_iter().find_map(|size| {
+                    let tag = size.opening_tag();
+                    (starts_with_tag(ahead, &tag)
+                        && contains_tag(&masked[index + tag.len()..], &size.closing_tag()))
+                    .then_some((Some(size), tag))
+                }),
+            };
+            if let Some((size, tag)) = toggled {
+                push_segment(&mut segments, &mut current);
+                current.size = size;
+                index += tag.len();
+                continue;
             }
-        } else {
-            current.push(ch);
         }
-    }
 
-    if !current.is_empty() {
-        segments.push(TextSegment {
-            text: current,
-            is_highlighted: in_highlight,
-        });
+        match ch {
+            '_' if current.underline || has_closing() => {
+                push_segment(&mut segments, &mut current);
+                current.underline = !current.underline;
+            }
+            '~' if current.strikethrough || has_closing() => {
+                push_segment(&mut segments, &mut current);
+                current.strikethrough = !current.strikethrough;
+            }
+            '*' if is_double_star
+                && (current.bold || rest[1..].windows(2).any(|pair| pair == ['*', '*'])) =>
+            {
+                push_segment(&mut segments, &mut current);
+                current.bold = !current.bold;
+                index += 1;
+            }
+            '*' if current.italic || has_closing() => {
+                push_segment(&mut segments, &mut current);
+                current.italic = !current.italic;
+            }
+            _ => current.text.push(ch),
+        }
+        index += 1;
     }
 
-    segments
+    let state = current.with_text(String::new());
+    push_segment(&mut segments, &mut current);
+
+    (segments, state)
 }
 
 pub fn get_scaled_font(font: &FontVec, font_size: u32) -> PxScaleFont<&FontVec> {
@@ -59,79 +323,639 @@ pub fn get_scaled_font(font: &FontVec, font_size: u32) -> PxScaleFont<&FontVec>
     })
 }
 
-fn measure_text_width(text: &str, scaled_font: PxScaleFont<&FontVec>) -> u32 {
-    text.chars().fold(0, |acc, c| {
-        let glyph_id = scaled_font.glyph_id(c);
-        acc + scaled_font.h_advance(glyph_id).ceil() as u32
-    })
+fn is_emoji_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{2300}'..='\u{23FF}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2B00}'..='\u{2BFF}'
+            | '\u{1F000}'..='\u{1FAFF}'
+    )
+}
+
+/// 变体选择符与零宽连接符，不进行字形组合时不绘制且不占宽度
+fn is_invisible_char(c: char) -> bool {
+    matches!(c, '\u{200D}' | '\u{FE0E}' | '\u{FE0F}')
+}
+
+/// 文字使用的字体，emoji 优先使用单独配置的 emoji 字体
+#[derive(Clone, Copy)]
+pub struct TextFonts<'a> {
+    pub main: &'a FontVec,
+    pub emoji: Option<&'a FontVec>,
+}
+
+impl<'a> TextFonts<'a> {
+    pub fn new(main: &'a FontVec, emoji: Option<&'a FontVec>) -> Self {
+        TextFonts { main, emoji }
+    }
+
+    /// 按字号缩放，并换算文字区域配置中的字间距与制表位宽度
+    pub fn scaled(&self, font_size: u32, config: &TextAreaConfig) -> ScaledFonts<'a> {
+        ScaledFonts {
+            main: get_scaled_font(self.main, font_size),
+            emoji: self.emoji.map(|font| get_scaled_font(font, font_size)),
+            letter_spacing: (font_size as f32 * config.letter_spacing).round() as i32,
+            tab_width: config.tab_width,
+            bold_offset: (font_size as f32 / 24.0).round().max(1.0) as u32,
+            italic_overhang: (get_scaled_font(self.main, font_size).ascent() * ITALIC_SHEAR).ceil()
+                as u32,
+        }
+    }
+}
+
+/// 按同一字号缩放后的各字体，两者的行高一致
+#[derive(Clone, Copy)]
+pub struct ScaledFonts<'a> {
+    pub main: PxScaleFont<&'a FontVec>,
+    pub emoji: Option<PxScaleFont<&'a FontVec>>,
+    /// 每个字符步进后追加的间距(像素)，可为负数
+    pub letter_spacing: i32,
+    /// 制表位间隔，以空格宽度为单位
+    pub tab_width: u32,
+    /// 模拟粗体时重复绘制的最大错位距离，每个字符的步进随之增加
+    pub bold_offset: u32,
+    /// 模拟斜体时字形顶部向右伸出的距离，在每段连续斜体文字之后预留
+    pub italic_overhang: u32,
+}
+
+impl<'a> ScaledFonts<'a> {
+    /// emoji 字体中存在该字符时使用 emoji 字体，否则使用主字体
+    pub fn is_emoji(&self, c: char) -> bool {
+        is_emoji_char(c) && self.emoji.is_some_and(|font| font.glyph_id(c).0 != 0)
+    }
+
+    pub fn get(&self, emoji: bool) -> PxScaleFont<&'a FontVec> {
+        match self.emoji {
+            Some(font) if emoji => font,
+            _ => self.main,
+        }
+    }
+
+    pub fn for_char(&self, c: char) -> PxScaleFont<&'a FontVec> {
+        self.get(self.is_emoji(c))
+    }
+
+    /// 文本段使用的字体，设置了相对字号时按比例缩放
+    pub fn for_segment(&self, segment: &TextSegment) -> ScaledFonts<'a> {
+        match segment.size {
+            Some(size) => self.resized(size.factor()),
+            None => *self,
+        }
+    }
+
+    /// 按比例缩放字体及随字号变化的间距
+    pub fn resized(&self, factor: f32) -> ScaledFonts<'a> {
+        let resize = |font: PxScaleFont<&'a FontVec>| {
+            font.font.as_scaled(PxScale {
+                x: font.scale.x * factor,
+                y: font.scale.y * factor,
+            })
+        };
+        let main = resize(self.main);
+        ScaledFonts {
+            main,
+            emoji: self.emoji.map(resize),
+            letter_spacing: (self.letter_spacing as f32 * factor).round() as i32,
+            tab_width: self.tab_width,
+            bold_offset: (self.bold_offset as f32 * factor).round().max(1.0) as u32,
+            italic_overhang: (main.ascent() * ITALIC_SHEAR).ceil() as u32,
+        }
+    }
+
+    /// 将文字按使用的字体切分为连续的片段，返回各片段是否使用 emoji 字体，不可见字符不计入片段
+    pub fn split<'t>(&self, text: &'t str) -> Vec<(&'t str, bool)> {
+        let mut pieces: Vec<(&'t str, bool)> = Vec::new();
+        let mut start = 0;
+        let mut current = None;
+        for (index, c) in text.char_indices() {
+            let emoji = (!is_invisible_char(c)).then(|| self.is_emoji(c));
+            if emoji != current {
+                if let Some(emoji) = current {
+                    pieces.push((&text[start..index], emoji));
+                }
+                start = index;
+                current = emoji;
+            }
+        }
+        if let Some(emoji) = current {
+            pieces.push((&text[start..], emoji));
+        }
+        pieces
+    }
+}
+
+/// 字符沿书写方向的步进(含字间距)，竖排时使用纵向度量，字体没有纵向度量时以行高代替
+pub fn char_advance(fonts: &ScaledFonts, c: char, vertical: bool) -> u32 {
+    if is_invisible_char(c) {
+        return 0;
+    }
+    let scaled_font = fonts.for_char(c);
+    let glyph_id = scaled_font.glyph_id(c);
+    let advance = if vertical {
+        Some(scaled_font.v_advance(glyph_id))
+            .filter(|&advance| advance > 0.0)
+            .unwrap_or_else(|| scaled_font.height())
+    } else {
+        scaled_font.h_advance(glyph_id)
+    };
+    (advance.ceil() as i32 + fonts.letter_spacing).max(0) as u32
+}
+
+/// 字素簇(如 emoji 序列、国旗)的步进，不进行字形组合时为其中各字符步进之和
+fn cluster_advance(fonts: &ScaledFonts, cluster: &str, vertical: bool) -> u32 {
+    cluster
+        .chars()
+        .map(|c| char_advance(fonts, c, vertical))
+        .sum()
+}
+
+/// 字素簇位于行内 `position` 处时的步进，制表符步进到下一个制表位，其他字素簇与位置无关
+pub fn cluster_advance_at(
+    fonts: &ScaledFonts,
+    cluster: &str,
+    vertical: bool,
+    position: u32,
+) -> u32 {
+    let tab_stop = fonts.tab_width * char_advance(fonts, ' ', vertical);
+    match cluster {
+        "\t" if tab_stop > 0 => tab_stop - position % tab_stop,
+        _ => cluster_advance(fonts, cluster, vertical),
+    }
+}
+
+/// 模拟粗体额外占用的宽度，每个可见字符增加一次错位距离，竖排时不影响步进
+pub fn bold_extra(fonts: &ScaledFonts, segment: &TextSegment, text: &str, vertical: bool) -> u32 {
+    if !segment.bold || vertical {
+        return 0;
+    }
+    let visible = text
+        .chars()
+        .filter(|&c| c != '\t' && !is_invisible_char(c))
+        .count() as u32;
+    fonts.bold_offset * visible
+}
+
+/// 横排时相邻两个字符间的字距调整(像素)，仅在两者均使用主字体时生效，
+/// 字体没有字距调整表时为 0
+pub fn kerning(fonts: &ScaledFonts, prev: char, c: char) -> i32 {
+    let uses_main = |c: char| c != '\t' && !is_invisible_char(c) && !fonts.is_emoji(c);
+    if !uses_main(prev) || !uses_main(c) {
+        return 0;
+    }
+    let font = fonts.main;
+    font.kern(font.glyph_id(prev), font.glyph_id(c)).round() as i32
+}
+
+/// 文字沿书写方向的长度，横排时计入相邻字符间的字距调整
+pub fn measure_text(text: &str, fonts: &ScaledFonts, vertical: bool) -> u32 {
+    let mut prev = None;
+    let width: i32 = text
+        .graphemes(true)
+        .map(|cluster| {
+            let kern = match (prev, cluster.chars().next()) {
+                (Some(prev), Some(c)) if !vertical => kerning(fonts, prev, c),
+                _ => 0,
+            };
+            prev = cluster.chars().last();
+            cluster_advance(fonts, cluster, vertical) as i32 + kern
+        })
+        .sum();
+    width.max(0) as u32
+}
+
+/// 排版后的一行，竖排时为一列，`width` 为沿书写方向的长度
+pub struct TextLine {
+    pub segments: Vec<(TextSegment, u32)>,
+    /// 包括行首缩进在内的长度
+    pub width: u32,
+    /// 行首缩进，从右到左排列时位于行的右侧
+    pub indent: u32,
+    /// 行高与基线到行顶的距离，取行内各文本段字号中的最大值，横排时包括注音占用的高度
+    pub height: f32,
+    pub ascent: f32,
+    /// 计入行间距后到下一行的距离
+    pub spaced_height: u32,
+    /// 是否为输入文字中某一段的首行
+    pub is_paragraph_start: bool,
+    /// 是否从右到左排列
+    pub is_rtl: bool,
+}
+
+impl TextLine {
+    fn new(segments: Vec<(TextSegment, u32)>, fonts: &ScaledFonts, vertical: bool) -> Self {
+        let width = segments.iter().map(|(_, width)| width).sum();
+        let (height, ascent) = segments
+            .iter()
+            .map(|(segment, _)| fonts.for_segment(segment).main)
+            .fold((0.0f32, 0.0f32), |(height, ascent), font| {
+                (height.max(font.height()), ascent.max(font.ascent()))
+            });
+        // 注音位于所注文字上方，在行顶预留注音的高度，竖排时不显示注音
+        let ruby_height = segments
+            .iter()
+            .filter(|(segment, _)| segment.ruby.is_some() && !vertical)
+            .map(|(segment, _)| ruby_fonts(fonts, segment).main.height())
+            .fold(0.0f32, f32::max);
+        TextLine {
+            segments,
+            width,
+            indent: 0,
+            height: height + ruby_height,
+            ascent: ascent + ruby_height,
+            spaced_height: 0,
+            is_paragraph_start: false,
+            is_rtl: false,
+        }
+    }
+
+    fn empty(fonts: &ScaledFonts) -> Self {
+        TextLine::new(vec![(TextSegment::default(), 0)], fonts, false)
+    }
+
+    /// 两端对齐时可以加宽的位置，按顺序给出(文本段序号, 字素簇在段内的字节偏移)，
+    /// 在该字素簇之前加宽：空白之后的字符前，以及中日韩文字与 emoji 的前后，不在空白之前加宽
+    pub fn justify_opportunities(&self) -> Vec<(usize, usize)> {
+        let is_cjk = |cluster: &str| !is_space_cluster(cluster) && !is_word_cluster(cluster);
+        let mut opportunities = Vec::new();
+        let mut prev: Option<&str> = None;
+        for (index, (segment, _)) in self.segments.iter().enumerate() {
+            let clusters: Vec<(usize, &str)> = match segment.ruby {
+                Some(_) => vec![(0, segment.text.as_str())],
+                None => segment.text.grapheme_indices(true).collect(),
+            };
+            for (offset, cluster) in clusters {
+                if prev.is_some_and(|prev| {
+                    !is_space_cluster(cluster)
+                        && (is_space_cluster(prev) || is_cjk(prev) || is_cjk(cluster))
+                }) {
+                    opportunities.push((index, offset));
+                }
+                prev = Some(cluster);
+            }
+        }
+        opportunities
+    }
+}
+
+/// 文本段注音使用的字体
+pub fn ruby_fonts<'a>(fonts: &ScaledFonts<'a>, segment: &TextSegment) -> ScaledFonts<'a> {
+    fonts.for_segment(segment).resized(RUBY_SCALE)
+}
+
+fn is_rtl_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}'
+    )
+}
+
+/// 按第一个强方向字符判断段落方向，没有强方向字符时视为从左到右
+fn is_rtl_paragraph(paragraph: &str, direction: TextDirection) -> bool {
+    match direction {
+        TextDirection::Ltr => false,
+        TextDirection::Rtl => true,
+        TextDirection::Auto => paragraph
+            .chars()
+            .find(|&c| is_rtl_char(c) || c.is_alphabetic())
+            .is_some_and(is_rtl_char),
+    }
+}
+
+/// 中日韩文字及 emoji 可在任意两个字素簇之间换行，不参与组成单词
+fn is_cjk_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1100}'..='\u{11FF}'
+            | '\u{2E80}'..='\u{A4CF}'
+            | '\u{AC00}'..='\u{D7AF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FE30}'..='\u{FE4F}'
+            | '\u{FF00}'..='\u{FFEF}'
+            | '\u{20000}'..='\u{3FFFF}'
+    )
+}
+
+fn is_space_cluster(cluster: &str) -> bool {
+    cluster.chars().all(char::is_whitespace)
+}
+
+fn is_word_cluster(cluster: &str) -> bool {
+    !is_space_cluster(cluster)
+        && !cluster
+            .chars()
+            .next()
+            .is_some_and(|c| is_cjk_char(c) || is_emoji_char(c))
+}
+
+/// 逐个放入字素簇构建各行，相邻且样式相同的字素簇合并为一个文本段
+struct LineBuilder<'f, 'a> {
+    fonts: &'f ScaledFonts<'a>,
+    vertical: bool,
+    max_width: u32,
+    /// 段落首行与其余各行的缩进
+    first_line_indent: u32,
+    hanging_indent: u32,
+    /// 当前行的缩进，计入 `width`
+    indent: u32,
+    lines: Vec<TextLine>,
+    current_line: Vec<(TextSegment, u32)>,
+    current_segment: TextSegment,
+    segment_width: u32,
+    width: u32,
+    /// 当前行末尾为斜体文字时其后需要预留的斜体伸出宽度
+    open_italic: u32,
+}
+
+impl<'f, 'a> LineBuilder<'f, 'a> {
+    fn new(fonts: &'f ScaledFonts<'a>, vertical: bool, max_width: u32, indent: i32) -> Self {
+        LineBuilder {
+            fonts,
+            vertical,
+            max_width,
+            first_line_indent: indent.max(0) as u32,
+            hanging_indent: indent.min(0).unsigned_abs(),
+            indent: 0,
+            lines: Vec::new(),
+            current_line: Vec::new(),
+            current_segment: TextSegment::default(),
+            segment_width: 0,
+            width: 0,
+            open_italic: 0,
+        }
+    }
+
+    /// 字素簇的步进，`prev` 为同一文本段中前一个字符，横排时计入两者间的字距调整
+    fn advance_at(
+        &self,
+        segment: &TextSegment,
+        cluster: &str,
+        position: u32,
+        prev: Option<char>,
+    ) -> u32 {
+        let fonts = self.fonts.for_segment(segment);
+        let kern = match (prev, cluster.chars().next()) {
+            (Some(prev), Some(c)) if !self.vertical => kerning(&fonts, prev, c),
+            _ => 0,
+        };
+        let advance = cluster_advance_at(&fonts, cluster, self.vertical, position)
+            + bold_extra(&fonts, segment, cluster, self.vertical);
+        (advance as i32 + kern).max(0) as u32
+    }
+
+    /// 放在当前行末尾时与之相邻的前一个字符，样式不同的文本段之间不进行字距调整
+    fn prev_char(&self, segment: &TextSegment) -> Option<char> {
+        if self.current_segment.same_style(segment) {
+            self.current_segment.text.chars().last()
+        } else {
+            None
+        }
+    }
+
+    /// 文本段为斜体时其后需要预留的宽度
+    fn italic_overhang(&self, segment: &TextSegment) -> u32 {
+        if self.vertical || !segment.italic {
+            0
+        } else {
+            self.fonts.for_segment(segment).italic_overhang
+        }
+    }
+
+    /// 字素簇放在当前行末尾时需要的宽度，包括其后可能需要预留的斜体伸出部分
+    fn required(&self, segment: &TextSegment, cluster: &str) -> u32 {
+        let overhang = if segment.italic {
+            self.italic_overhang(segment)
+        } else {
+            self.open_italic
+        };
+        self.advance_at(segment, cluster, self.width, self.prev_char(segment)) + overhang
+    }
+
+    fn fits(&self, width: u32) -> bool {
+        self.width + width <= self.max_width
+    }
+
+    fn close_italic(&mut self) {
+        let overhang = std::mem::take(&mut self.open_italic);
+        self.segment_width += overhang;
+        self.width += overhang;
+    }
+
+    fn push(&mut self, segment: &TextSegment, cluster: &str) {
+        if !segment.italic {
+            self.close_italic();
+        }
+        if !self.current_segment.same_style(segment) {
+            self.flush_segment();
+            self.current_segment = segment.with_text(String::new());
+        }
+        let advance = self.advance_at(segment, cluster, self.width, self.prev_char(segment));
+        self.current_segment.text.push_str(cluster);
+        self.segment_width += advance;
+        self.width += advance;
+        self.open_italic = self.italic_overhang(segment);
+    }
+
+    /// 放入字素簇，放不下时先换行
+    fn push_wrapping(&mut self, segment: &TextSegment, cluster: &str) {
+        if !self.fits(self.required(segment, cluster)) {
+            self.break_line();
+        }
+        self.push(segment, cluster);
+    }
+
+    fn flush_segment(&mut self) {
+        if !self.current_segment.text.is_empty() {
+            let text = std::mem::take(&mut self.current_segment.text);
+            let width = std::mem::take(&mut self.segment_width);
+            self.current_line
+                .push((self.current_segment.with_text(text), width));
+        }
+    }
+
+    fn break_line(&mut self) {
+        self.close_italic();
+        self.flush_segment();
+        if !self.current_line.is_empty() {
+            let segments = std::mem::take(&mut self.current_line);
+            let mut line = TextLine::new(segments, self.fonts, self.vertical);
+            line.indent = self.indent;
+            line.width += self.indent;
+            self.lines.push(line);
+        }
+        self.start_line(self.hanging_indent);
+    }
+
+    fn start_line(&mut self, indent: u32) {
+        self.indent = indent;
+        self.width = indent;
+    }
+
+    /// 放入单词及其前面的空白：放不下时整体换到下一行并省略换行处的空白，
+    /// 单词本身超过最大宽度时才在单词内部换行
+    fn push_word(
+        &mut self,
+        spaces: &mut Vec<(&TextSegment, &str)>,
+        word: &mut Vec<(&TextSegment, &str)>,
+    ) {
+        if word.is_empty() {
+            return;
+        }
+        // 从 `start` 处紧接着 `prev` 依次放置时的总宽度
+        let width_of = |clusters: &[(&TextSegment, &str)],
+                        start: u32,
+                        prev: Option<(&TextSegment, char)>|
+         -> u32 {
+            clusters
+                .iter()
+                .enumerate()
+                .fold(start, |position, (index, &(segment, cluster))| {
+                    let prev = match index {
+                        0 => prev,
+                        _ => {
+                            let (prev_segment, prev_cluster) = clusters[index - 1];
+                            prev_cluster.chars().last().map(|c| (prev_segment, c))
+                        }
+                    };
+                    let prev_char = prev
+                        .filter(|(prev_segment, _)| prev_segment.same_style(segment))
+                        .map(|(_, c)| c);
+                    position + self.advance_at(segment, cluster, position, prev_char)
+                })
+                - start
+        };
+        let line_end = self
+            .current_segment
+            .text
+            .chars()
+            .last()
+            .map(|c| (&self.current_segment, c));
+        let spaces_end = spaces
+            .last()
+            .and_then(|&(segment, cluster)| cluster.chars().last().map(|c| (segment, c)))
+            .or(line_end);
+        let overhang_of = |clusters: &[(&TextSegment, &str)]| -> u32 {
+            clusters
+                .iter()
+                .map(|(segment, _)| self.italic_overhang(segment))
+                .max()
+                .unwrap_or(0)
+        };
+        let spaces_width = width_of(spaces, self.width, line_end);
+        let word_width = width_of(word, self.width + spaces_width, spaces_end);
+        let overhang = self
+            .open_italic
+            .max(overhang_of(spaces))
+            .max(overhang_of(word));
+
+        if self.fits(spaces_width + word_width + overhang) {
+            for (segment, cluster) in spaces.drain(..).chain(word.drain(..)) {
+                self.push(segment, cluster);
+            }
+        } else if self.hanging_indent
+            + width_of(word, self.hanging_indent, None)
+            + overhang_of(word)
+            <= self.max_width
+        {
+            self.break_line();
+            spaces.clear();
+            for (segment, cluster) in word.drain(..) {
+                self.push(segment, cluster);
+            }
+        } else {
+            if self.fits(spaces_width) {
+                for (segment, cluster) in spaces.drain(..) {
+                    self.push(segment, cluster);
+                }
+            } else {
+                self.break_line();
+                spaces.clear();
+            }
+            for (segment, cluster) in word.drain(..) {
+                self.push_wrapping(segment, cluster);
+            }
+        }
+    }
 }
 
+/// `indent` 为正时缩进各段首行，为负时缩进各段除首行外的其余各行
+#[allow(clippy::too_many_arguments)]
 fn wrap_text(
     text: &str,
-    scaled_font: PxScaleFont<&FontVec>,
+    fonts: &ScaledFonts,
     max_width: u32,
-) -> Vec<Vec<(TextSegment, u32)>> {
-    let mut lines = Vec::new();
+    indent: i32,
+    direction: TextDirection,
+    vertical: bool,
+    word_wrap: bool,
+    delimiters: &[(&str, &str)],
+) -> Vec<TextLine> {
+    let mut builder = LineBuilder::new(fonts, vertical, max_width, indent);
 
     for paragraph in text.lines() {
+        let paragraph_start = builder.lines.len();
         if paragraph.is_empty() {
-            lines.push(vec![(TextSegment::default(), 0)]);
+            let mut line = TextLine::empty(fonts);
+            line.is_paragraph_start = true;
+            builder.lines.push(line);
             continue;
         }
+        builder.start_line(builder.first_line_indent);
 
-        let segments = parse_highlighted_text(paragraph);
-        let mut current_line = Vec::new();
-        let mut current_segment = TextSegment::default();
-        let mut line_width = 0;
-
-        for segment in segments {
-            for ch in segment.text.chars() {
-                let test_char = ch.to_string();
-                let char_width = measure_text_width(&test_char, scaled_font);
+        // 按字素簇换行，避免多码点组成的 emoji 被拆分到两行，带注音的文本段整体作为一个单位
+        let segments = parse_highlighted_text(paragraph, delimiters);
+        let clusters = segments.iter().flat_map(|segment| {
+            let clusters: Vec<&str> = match segment.ruby {
+                Some(_) => vec![segment.text.as_str()],
+                None => segment.text.graphemes(true).collect(),
+            };
+            clusters.into_iter().map(move |cluster| (segment, cluster))
+        });
 
-                if line_width + char_width <= max_width {
-                    if current_segment.is_highlighted == segment.is_highlighted {
-                        current_segment.text.push(ch);
-                    } else {
-                        if !current_segment.text.is_empty() {
-                            let seg_width = measure_text_width(&current_segment.text, scaled_font);
-                            current_line.push((current_segment, seg_width));
-                        }
-                        current_segment = TextSegment {
-                            text: ch.to_string(),
-                            is_highlighted: segment.is_highlighted,
-                        };
-                    }
-                    line_width += char_width;
+        if word_wrap {
+            // 单词可跨越多个文本段，例如部分字母带有下划线
+            let mut spaces = Vec::new();
+            let mut word = Vec::new();
+            for (segment, cluster) in clusters {
+                if is_word_cluster(cluster) {
+                    word.push((segment, cluster));
+                    continue;
+                }
+                builder.push_word(&mut spaces, &mut word);
+                if is_space_cluster(cluster) {
+                    spaces.push((segment, cluster));
                 } else {
-                    if !current_segment.text.is_empty() {
-                        let seg_width = measure_text_width(&current_segment.text, scaled_font);
-                        current_line.push((current_segment, seg_width));
-                    }
-                    if !current_line.is_empty() {
-                        lines.push(current_line);
-                    }
-                    current_line = Vec::new();
-                    current_segment = TextSegment {
-                        text: ch.to_string(),
-                        is_highlighted: segment.is_highlighted,
-                    };
-                    line_width = char_width;
+                    word.push((segment, cluster));
+                    builder.push_word(&mut spaces, &mut word);
+                }
+            }
+            builder.push_word(&mut spaces, &mut word);
+            // 段末的空白放得下时保留
+            for (segment, cluster) in spaces {
+                if builder.fits(builder.required(segment, cluster)) {
+                    builder.push(segment, cluster);
                 }
             }
+        } else {
+            for (segment, cluster) in clusters {
+                builder.push_wrapping(segment, cluster);
+            }
         }
+        builder.break_line();
 
-        if !current_segment.text.is_empty() {
-            let seg_width = measure_text_width(&current_segment.text, scaled_font);
-            current_line.push((current_segment, seg_width));
+        let lines = &mut builder.lines;
+        if let Some(line) = lines.get_mut(paragraph_start) {
+            line.is_paragraph_start = true;
         }
-        if !current_line.is_empty() {
-            lines.push(current_line);
+        let is_rtl = is_rtl_paragraph(paragraph, direction);
+        for line in &mut lines[paragraph_start..] {
+            line.is_rtl = is_rtl;
         }
     }
 
+    let mut lines = builder.lines;
     if lines.is_empty() {
-        lines.push(vec![(TextSegment::default(), 0)]);
+        lines.push(TextLine::empty(fonts));
     }
 
     lines
@@ -139,70 +963,580 @@ fn wrap_text(
 
 pub struct PreparedTextarea {
     pub font_size: u32,
-    pub lines: Vec<Vec<(TextSegment, u32)>>,
-    pub spaced_line_height: u32,
+    pub lines: Vec<TextLine>,
+    pub paragraph_gap: u32,
+    /// 文本块在图片上的宽度与高度，竖排时宽度为各列的总宽度
+    pub block_width: u32,
     pub block_height: u32,
+    /// 是否截断了放不下的文字并以省略号结尾
+    pub truncated: bool,
 }
 
-pub fn prepare_textarea(
+/// 扣除描边与阴影超出字形的部分后文字可用的区域，返回左上角坐标与区域大小
+pub fn usable_region(config: &TextAreaConfig) -> ([i32; 2], [u32; 2]) {
+    let (dx, dy) = config.shadow_offset;
+    let stroke = config.stroke.as_ref().map_or(0, |s| s.width as i32);
+    let [x, y] = config.pixel_position();
+    let [width, height] = config.pixel_size();
+
+    let left = stroke.max(-dx);
+    let right = stroke.max(dx);
+    let top = stroke.max(-dy);
+    let bottom = stroke.max(dy);
+
+    (
+        [x + left, y + top],
+        [
+            width.saturating_sub((left + right) as u32),
+            height.saturating_sub((top + bottom) as u32),
+        ],
+    )
+}
+
+/// 字号搜索的约束条件，`fits` 判断候选排版是否满足约束
+pub struct FitConstraints {
+    pub width: u32,
+    pub height: u32,
+    pub min_font_size: u32,
+    pub max_font_size: u32,
+    pub max_lines: Option<usize>,
+}
+
+impl FitConstraints {
+    pub fn from_config(config: &TextAreaConfig) -> Self {
+        let (_, [width, height]) = usable_region(config);
+        let max_font_size = config
+            .max_font_size
+            .map_or(height, |max_size| max_size.min(height))
+            .max(1);
+        let min_font_size = config.min_font_size.unwrap_or(1).clamp(1, max_font_size);
+
+        FitConstraints {
+            width,
+            height,
+            min_font_size,
+            max_font_size,
+            max_lines: config.max_lines,
+        }
+    }
+
+    pub fn fits(&self, candidate: &PreparedTextarea) -> bool {
+        candidate.block_width <= self.width
+            && candidate.block_height <= self.height
+            && !self.exceeds_lines(candidate)
+    }
+
+    fn exceeds_lines(&self, candidate: &PreparedTextarea) -> bool {
+        self.max_lines
+            .is_some_and(|max_lines| candidate.lines.len() > max_lines)
+    }
+}
+
+fn layout_textarea(
     text: &str,
-    font: &FontVec,
-    region_width: u32,
-    region_height: u32,
-    max_font_size: Option<u32>,
-    line_spacing: f32,
+    fonts: TextFonts,
+    font_size: u32,
+    constraints: &FitConstraints,
+    config: &TextAreaConfig,
 ) -> PreparedTextarea {
-    let max_size = if let Some(max_h) = max_font_size {
-        max_h.min(region_height)
-    } else {
-        region_height
+    let scaled_fonts = fonts.scaled(font_size, config);
+    let delimiters = highlight_delimiters(config);
+    // 竖排时按区域高度分列，不区分文字方向
+    let mut lines = match config.writing_mode {
+        WritingMode::Horizontal => wrap_text(
+            text,
+            &scaled_fonts,
+            constraints.width,
+            config.first_line_indent,
+            config.direction,
+            false,
+            config.word_wrap,
+            &delimiters,
+        ),
+        WritingMode::VerticalRl => wrap_text(
+            text,
+            &scaled_fonts,
+            constraints.height,
+            config.first_line_indent,
+            TextDirection::Ltr,
+            true,
+            config.word_wrap,
+            &delimiters,
+        ),
     };
 
-    let mut lo = 1;
-    let mut hi = max_size;
-    let mut best_size = 1;
-    let mut best_lines = vec![vec![(
-        TextSegment {
-            text: text.to_string(),
-            is_highlighted: false,
-        },
-        0,
-    )]];
-    let mut best_spaced_line_height = 1;
-    let mut best_block_height = 1;
+    // 行距按各行自身的行高计算，行间距小于等于 -1 时行距仍至少为 1 像素，避免各行重叠在同一位置
+    for line in &mut lines {
+        line.spaced_height = ((line.height * (1.0 + config.line_spacing)).ceil() as u32).max(1);
+    }
+
+    let paragraph_gap = (scaled_fonts.main.height() * config.paragraph_spacing).ceil() as u32;
+
+    let line_length = lines.iter().map(|line| line.width).max().unwrap_or(0);
+
+    // 首行之前不添加段间距，末行之后不添加行间距
+    let paragraph_count = lines
+        .iter()
+        .skip(1)
+        .filter(|line| line.is_paragraph_start)
+        .count() as u32;
+    let last_line_spacing = lines
+        .last()
+        .map_or(0, |line| (line.height * config.line_spacing).ceil() as u32);
+    let lines_extent = lines
+        .iter()
+        .map(|line| line.spaced_height)
+        .sum::<u32>()
+        .saturating_sub(last_line_spacing)
+        + paragraph_gap * paragraph_count;
+    let (block_width, block_height) = match config.writing_mode {
+        WritingMode::Horizontal => (line_length, lines_extent),
+        WritingMode::VerticalRl => (lines_extent, line_length),
+    };
+
+    PreparedTextarea {
+        font_size,
+        lines,
+        paragraph_gap,
+        block_width,
+        block_height,
+        truncated: false,
+    }
+}
+
+/// 在约束的字号范围内二分搜索满足 `fits` 的最大字号，均不满足时使用最小字号
+pub fn fit_font_size<L, F>(constraints: &FitConstraints, layout: L, fits: F) -> PreparedTextarea
+where
+    L: Fn(u32) -> PreparedTextarea,
+    F: Fn(&PreparedTextarea) -> bool,
+{
+    let min_font_size = constraints.min_font_size.max(1);
+    if min_font_size >= constraints.max_font_size {
+        return layout(min_font_size);
+    }
+
+    let mut lo = min_font_size;
+    let mut hi = constraints.max_font_size;
+    let mut best = None;
 
     while lo <= hi {
         let mid = u32::midpoint(lo, hi);
-        let scaled_font = get_scaled_font(font, mid);
-        let lines = wrap_text(text, scaled_font, region_width);
+        let candidate = layout(mid);
 
-        let line_height = scaled_font.height();
-        let spaced_line_height = (line_height * (1.0 + line_spacing)).ceil() as u32;
+        if fits(&candidate) {
+            best = Some(candidate);
+            lo = mid + 1;
+        } else {
+            hi = mid - 1;
+        }
+    }
 
-        let max_width = lines
-            .iter()
-            .map(|line| line.iter().map(|(_, width)| width).sum())
-            .max()
-            .unwrap_or(0);
-
-        let total_height =
-            spaced_line_height * lines.len() as u32 - (line_height * line_spacing).ceil() as u32;
-
-        if max_width <= region_width && total_height <= region_height {
-            best_size = mid;
-            best_lines = lines;
-            best_spaced_line_height = spaced_line_height;
-            best_block_height = total_height;
+    best.unwrap_or_else(|| layout(min_font_size))
+}
+
+pub fn prepare_textarea(
+    text: &str,
+    fonts: TextFonts,
+    config: &TextAreaConfig,
+    constraints: &FitConstraints,
+) -> PreparedTextarea {
+    let prepared = fit_font_size(
+        constraints,
+        |font_size| layout_textarea(text, fonts, font_size, constraints, config),
+        |candidate| constraints.fits(candidate),
+    );
+
+    // 超出行数上限时不论 `overflow` 如何均以省略号截断，避免多出的行被直接丢弃
+    let ellipsis = match config.overflow {
+        TextOverflow::Ellipsis => !constraints.fits(&prepared),
+        TextOverflow::Shrink => constraints.exceeds_lines(&prepared),
+    };
+    if ellipsis {
+        layout_with_ellipsis(text, fonts, prepared.font_size, constraints, config)
+    } else {
+        prepared
+    }
+}
+
+/// 截取前 `end` 字节的文字并在末尾加上省略号，被截断的样式标记在省略号后补齐
+fn ellipsize(text: &str, end: usize, delimiters: &[(&str, &str)]) -> String {
+    let kept = &text[..end];
+    let (_, state) = parse_markup(text, kept.chars().count(), delimiters);
+    let mut result = kept.trim_end().to_string();
+    result.push('…');
+    result.push_str(&state.closing_markers());
+    result
+}
+
+/// 在给定字号下二分搜索放得下的最长前缀(按字素簇)，省略号计入排版，不会超出区域
+fn layout_with_ellipsis(
+    text: &str,
+    fonts: TextFonts,
+    font_size: u32,
+    constraints: &FitConstraints,
+    config: &TextAreaConfig,
+) -> PreparedTextarea {
+    let delimiters = highlight_delimiters(config);
+    let layout = |end: usize| {
+        let text = ellipsize(text, end, &delimiters);
+        layout_textarea(&text, fonts, font_size, constraints, config)
+    };
+    let boundaries: Vec<usize> = text.grapheme_indices(true).map(|(i, _)| i).collect();
+
+    // 保留的字素簇数量，全部保留即为原文，此时已确定放不下
+    let mut lo = 1;
+    let mut hi = boundaries.len().saturating_sub(1);
+    let mut best = None;
+    while lo <= hi {
+        let mid = usize::midpoint(lo, hi);
+        let candidate = layout(boundaries[mid]);
+        if constraints.fits(&candidate) {
+            best = Some(candidate);
             lo = mid + 1;
         } else {
             hi = mid - 1;
         }
     }
 
-    PreparedTextarea {
-        font_size: best_size,
-        lines: best_lines,
-        spaced_line_height: best_spaced_line_height,
-        block_height: best_block_height,
+    let mut prepared = best.unwrap_or_else(|| layout(0));
+    prepared.truncated = true;
+    prepared
+}
+
+/// 文字排版结果概要
+pub struct FitReport {
+    pub font_size: u32,
+    pub truncated: bool,
+}
+
+/// 仅执行排版而不绘制，检查文字在最小字号下是否仍会超出文字区域
+pub fn check_fit(text: &str, fonts: TextFonts, config: &TextAreaConfig) -> FitReport {
+    let constraints = FitConstraints::from_config(config);
+    let prepared = prepare_textarea(text, fonts, config, &constraints);
+
+    FitReport {
+        font_size: prepared.font_size,
+        truncated: prepared.truncated || !constraints.fits(&prepared),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::sync::OnceLock;
+
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    pub(crate) fn test_font() -> &'static FontVec {
+        static FONT: OnceLock<FontVec> = OnceLock::new();
+        FONT.get_or_init(|| {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../data/fonts/font.ttf");
+            FontVec::try_from_vec(fs::read(path).unwrap()).unwrap()
+        })
+    }
+
+    /// 以给定大小构造文字区域配置，`extra` 中的字段覆盖默认值
+    pub(crate) fn textarea(size: [i32; 2], extra: Value) -> TextAreaConfig {
+        let mut config = json!({ "position": [0, 0], "size": size, "font_color": "black" });
+        if let (Some(config), Value::Object(extra)) = (config.as_object_mut(), extra) {
+            config.extend(extra);
+        }
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn prepare(text: &str, config: &TextAreaConfig) -> PreparedTextarea {
+        let constraints = FitConstraints::from_config(config);
+        prepare_textarea(
+            text,
+            TextFonts::new(test_font(), None),
+            config,
+            &constraints,
+        )
+    }
+
+    #[test]
+    fn zero_size_region_clamps_font_size() {
+        let config = textarea([0, 0], json!({}));
+        let constraints = FitConstraints::from_config(&config);
+        assert_eq!([constraints.width, constraints.height], [0, 0]);
+        assert_eq!(constraints.min_font_size, 1);
+        assert_eq!(constraints.max_font_size, 1);
+
+        // 默认缩小字号而不截断，最小字号下仍放不下
+        let prepared = prepare("你好，世界", &config);
+        assert_eq!(prepared.font_size, 1);
+        assert!(!constraints.fits(&prepared));
+
+        let report = check_fit("你好，世界", TextFonts::new(test_font(), None), &config);
+        assert!(report.truncated);
+    }
+
+    #[test]
+    fn one_pixel_region_uses_minimum_font_size() {
+        let config = textarea([1, 1], json!({ "min_font_size": 8 }));
+        let constraints = FitConstraints::from_config(&config);
+        assert_eq!(constraints.min_font_size, 1);
+        assert_eq!(constraints.max_font_size, 1);
+
+        let prepared = prepare("a", &config);
+        assert_eq!(prepared.font_size, 1);
+    }
+
+    #[test]
+    fn region_smaller_than_glyph_truncates() {
+        let config = textarea([6, 6], json!({ "min_font_size": 12 }));
+        let constraints = FitConstraints::from_config(&config);
+        assert_eq!(constraints.max_font_size, 6);
+        assert_eq!(constraints.min_font_size, 6);
+
+        let prepared = prepare("你好，世界", &config);
+        assert_eq!(prepared.font_size, 6);
+        assert!(!constraints.fits(&prepared));
+
+        // 以省略号截断时至少保留省略号所在的一行
+        let config = textarea(
+            [6, 6],
+            json!({ "min_font_size": 12, "overflow": "ellipsis" }),
+        );
+        let prepared = prepare("你好，世界", &config);
+        assert!(prepared.truncated);
+        assert_eq!(prepared.lines.len(), 1);
+    }
+
+    /// 文本块边长与字号成正比的模拟排版
+    fn scaled_layout(font_size: u32) -> PreparedTextarea {
+        PreparedTextarea {
+            font_size,
+            lines: Vec::new(),
+            paragraph_gap: 0,
+            block_width: font_size * 10,
+            block_height: font_size * 2,
+            truncated: false,
+        }
+    }
+
+    fn constraints(width: u32, height: u32, min: u32, max: u32) -> FitConstraints {
+        FitConstraints {
+            width,
+            height,
+            min_font_size: min,
+            max_font_size: max,
+            max_lines: None,
+        }
+    }
+
+    #[test]
+    fn fit_font_size_finds_largest_fitting_size() {
+        let constraints = constraints(95, 100, 1, 100);
+        let prepared = fit_font_size(&constraints, scaled_layout, |c| constraints.fits(c));
+        assert_eq!(prepared.font_size, 9);
+    }
+
+    #[test]
+    fn fit_font_size_falls_back_to_minimum() {
+        let constraints = constraints(5, 100, 3, 100);
+        let prepared = fit_font_size(&constraints, scaled_layout, |c| constraints.fits(c));
+        assert_eq!(prepared.font_size, 3);
+    }
+
+    #[test]
+    fn fit_font_size_skips_search_for_fixed_size() {
+        let calls = std::cell::Cell::new(0);
+        let constraints = constraints(1000, 1000, 20, 20);
+        let prepared = fit_font_size(
+            &constraints,
+            |font_size| {
+                calls.set(calls.get() + 1);
+                scaled_layout(font_size)
+            },
+            |_| unreachable!(),
+        );
+        assert_eq!(prepared.font_size, 20);
+        assert_eq!(calls.get(), 1);
+    }
+
+    /// 改写前 `prepare_textarea` 的字号搜索：逐字符按向上取整的字宽换行，
+    /// 二分查找宽度与高度均不超出区域的最大字号
+    fn baseline_font_size(
+        text: &str,
+        [region_width, region_height]: [u32; 2],
+        max_font_size: Option<u32>,
+        line_spacing: f32,
+    ) -> u32 {
+        let font = test_font();
+        let char_width = |c: char, scaled_font: PxScaleFont<&FontVec>| {
+            scaled_font.h_advance(scaled_font.glyph_id(c)).ceil() as u32
+        };
+        let wrap = |scaled_font: PxScaleFont<&FontVec>| {
+            let mut widths = Vec::new();
+            for paragraph in text.lines() {
+                let mut line_width = 0;
+                let mut line_started = false;
+                for c in paragraph.chars() {
+                    let width = char_width(c, scaled_font);
+                    if line_width + width <= region_width {
+                        line_width += width;
+                    } else {
+                        if line_started {
+                            widths.push(line_width);
+                        }
+                        line_width = width;
+                    }
+                    line_started = true;
+                }
+                widths.push(line_width);
+            }
+            widths
+        };
+
+        let (mut lo, mut hi, mut best) = (
+            1,
+            max_font_size.map_or(region_height, |max| max.min(region_height)),
+            1,
+        );
+        while lo <= hi {
+            let mid = u32::midpoint(lo, hi);
+            let scaled_font = get_scaled_font(font, mid);
+            let widths = wrap(scaled_font);
+            let line_height = scaled_font.height();
+            let spaced_line_height = (line_height * (1.0 + line_spacing)).ceil() as u32;
+            let total_height = spaced_line_height * widths.len() as u32
+                - (line_height * line_spacing).ceil() as u32;
+            if widths.iter().all(|&width| width <= region_width) && total_height <= region_height {
+                best = mid;
+                lo = mid + 1;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn prepare_textarea_matches_baseline_font_sizes() {
+        // 只使用改写前已有的配置字段
+        let cases = [
+            ("你好", [200, 100], None, 0.0),
+            ("一二三四五六七八九十", [100, 200], None, 0.0),
+            ("第一行\n第二行\n第三行", [150, 150], Some(40), 0.0),
+            ("第一行\n第二行\n第三行", [150, 150], None, 0.5),
+            ("天气【真好】啊", [160, 90], Some(60), 0.15),
+            ("第一段\n\n第三段", [120, 120], None, 0.2),
+            (
+                "一二三四五六七八九十一二三四五六七八九十",
+                [300, 60],
+                None,
+                0.0,
+            ),
+        ];
+        for (text, size, max_font_size, line_spacing) in cases {
+            let mut extra = json!({ "line_spacing": line_spacing, "highlight": "red" });
+            if let Some(max_font_size) = max_font_size {
+                extra["max_font_size"] = json!(max_font_size);
+            }
+            let config = textarea(size.map(|side| side as i32), extra);
+            assert_eq!(
+                prepare(text, &config).font_size,
+                baseline_font_size(text, size, max_font_size, line_spacing),
+                "{text:?} in {size:?}"
+            );
+        }
+    }
+
+    fn layout(text: &str, font_size: u32, config: &TextAreaConfig) -> PreparedTextarea {
+        let constraints = FitConstraints::from_config(config);
+        layout_textarea(
+            text,
+            TextFonts::new(test_font(), None),
+            font_size,
+            &constraints,
+            config,
+        )
+    }
+
+    #[test]
+    fn paragraph_spacing_adds_gap_between_paragraphs() {
+        let text = "第一段\n第二段\n第三段";
+        let plain = layout(text, 20, &textarea([200, 200], json!({})));
+        let spaced = layout(
+            text,
+            20,
+            &textarea([200, 200], json!({ "paragraph_spacing": 0.5 })),
+        );
+
+        assert_eq!(plain.paragraph_gap, 0);
+        assert!(spaced.paragraph_gap > 0);
+        assert_eq!(
+            spaced
+                .lines
+                .iter()
+                .filter(|line| line.is_paragraph_start)
+                .count(),
+            3
+        );
+        // 首段之前不加段间距
+        assert_eq!(
+            spaced.block_height,
+            plain.block_height + spaced.paragraph_gap * 2
+        );
+    }
+
+    #[test]
+    fn paragraph_spacing_skips_wrapped_lines() {
+        let text = "一二三四五六七八";
+        let plain = layout(text, 20, &textarea([80, 200], json!({})));
+        let spaced = layout(
+            text,
+            20,
+            &textarea([80, 200], json!({ "paragraph_spacing": 0.5 })),
+        );
+
+        assert_eq!(spaced.lines.len(), 2);
+        assert!(!spaced.lines[1].is_paragraph_start);
+        assert_eq!(spaced.block_height, plain.block_height);
+    }
+
+    #[test]
+    fn paragraph_spacing_is_included_in_fit() {
+        let text = "第一段\n第二段\n第三段";
+        let plain = textarea([200, 120], json!({}));
+        let spaced = textarea([200, 120], json!({ "paragraph_spacing": 1.0 }));
+        let prepared = prepare(text, &spaced);
+
+        assert!(prepared.font_size < prepare(text, &plain).font_size);
+        assert!(FitConstraints::from_config(&spaced).fits(&prepared));
+    }
+
+    #[test]
+    fn usable_region_subtracts_stroke_on_all_sides() {
+        let config = textarea(
+            [200, 100],
+            json!({ "stroke": { "color": "black", "width": 5 } }),
+        );
+        assert_eq!(usable_region(&config), ([5, 5], [190, 90]));
+    }
+
+    #[test]
+    fn usable_region_subtracts_shadow_on_its_side() {
+        let config = textarea([200, 100], json!({ "shadow_offset": [6, -4] }));
+        assert_eq!(usable_region(&config), ([0, 4], [194, 96]));
+
+        // 阴影偏移小于描边宽度时以描边为准
+        let config = textarea(
+            [200, 100],
+            json!({ "shadow_offset": [3, 8], "stroke": { "color": "black", "width": 5 } }),
+        );
+        assert_eq!(usable_region(&config), ([5, 5], [190, 87]));
+    }
+
+    #[test]
+    fn usable_region_saturates_for_thick_stroke() {
+        let config = textarea(
+            [8, 8],
+            json!({ "stroke": { "color": "black", "width": 10 } }),
+        );
+        assert_eq!(usable_region(&config), ([10, 10], [0, 0]));
     }
 }
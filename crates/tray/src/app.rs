@@ -2,10 +2,11 @@ use std::collections::HashMap;
 use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::Instant;
 
 use anyhow::{Error, Result};
 use global_hotkey::{GlobalHotKeyEvent, HotKeyState};
-use rfd::{MessageDialog, MessageLevel};
+use rfd::{MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
 use tray_icon::menu::{MenuEvent, MenuId};
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
@@ -14,28 +15,38 @@ use winit::event_loop::{ActiveEventLoop, EventLoop};
 use imagebox_core::DataManager;
 use winit::window::WindowId;
 
-use crate::config::{Config, ConfigManager, ProcessMode};
-use crate::keyboard::{HotkeyManager, check_whitelist, start_keyboard_listener};
+use crate::config::{Config, ConfigChanges, ConfigManager, ProcessMode};
+use crate::keyboard::{
+    HotkeyManager, check_whitelist, reset_modifier_keys, start_keyboard_listener,
+};
 use crate::processor::process_image;
+use crate::stats::Stats;
 use crate::tray::{ControlMessage, TrayMenu, create_tray_menu};
 
 pub enum UserEvent {
     ConfigReloaded,
     TrayMenuEvent(MenuId),
     HotkeyEvent(GlobalHotKeyEvent),
-    EnterKeyPressed,
+    EnterKeyPressed(ProcessMode),
 }
 
 pub struct App {
     data_manager: Arc<DataManager>,
     is_processing: Arc<Mutex<bool>>,
+    keyboard_hook: bool,
+    last_used: Arc<Mutex<HashMap<String, Instant>>>,
+    stats: Arc<Mutex<Stats>>,
     tray_menu: TrayMenu,
     hotkey_manager: HotkeyManager,
     config_manager: Arc<RwLock<ConfigManager>>,
 }
 
 impl App {
-    pub fn new(work_dir: &Path, event_loop: &EventLoop<UserEvent>) -> Result<Self> {
+    pub fn new(
+        work_dir: &Path,
+        event_loop: &EventLoop<UserEvent>,
+        no_intercept: bool,
+    ) -> Result<Self> {
         let show_resource_error = |e: Error| {
             MessageDialog::new()
                 .set_level(MessageLevel::Error)
@@ -76,9 +87,15 @@ impl App {
 
         let config = config_manager.get_config();
 
-        let tray_menu = create_tray_menu(&characters, config)?;
+        let tray_menu = create_tray_menu(&characters, config, &work_dir.join("data/tray.png"))?;
 
-        let hotkey_manager = HotkeyManager::new(config)?;
+        // 安全模式下不安装全局键盘钩子，仅通过快捷键生成
+        let keyboard_hook = !no_intercept && !config.disable_keyboard_hook;
+        if !keyboard_hook {
+            tray_menu.disable_intercept();
+        }
+
+        let hotkey_manager = HotkeyManager::new(config, data_manager.get_characters())?;
 
         let config_manager = Arc::new(RwLock::new(config_manager));
         let is_processing = Arc::new(Mutex::new(false));
@@ -96,14 +113,21 @@ impl App {
             proxy_hotkey.send_event(UserEvent::HotkeyEvent(event)).ok();
         }));
 
-        let proxy_keyboard = event_loop.create_proxy();
-        start_keyboard_listener(config_manager.clone(), is_processing.clone(), move || {
-            proxy_keyboard.send_event(UserEvent::EnterKeyPressed).ok();
-        });
+        if keyboard_hook {
+            let proxy_keyboard = event_loop.create_proxy();
+            start_keyboard_listener(config_manager.clone(), is_processing.clone(), move |mode| {
+                proxy_keyboard
+                    .send_event(UserEvent::EnterKeyPressed(mode))
+                    .ok();
+            });
+        }
 
         Ok(Self {
             data_manager,
             is_processing,
+            keyboard_hook,
+            last_used: Arc::new(Mutex::new(HashMap::new())),
+            stats: Arc::new(Mutex::new(Stats::default())),
             tray_menu,
             hotkey_manager,
             config_manager,
@@ -113,18 +137,37 @@ impl App {
     fn handle_reload_config(&mut self, old_config: &Config) {
         let config_manager = self.config_manager.read().unwrap();
         let new_config = config_manager.get_config();
+        let changes = ConfigChanges::between(old_config, new_config);
 
-        self.hotkey_manager.update(new_config);
-
-        self.tray_menu.set_process_mode(new_config.process_mode);
-        self.tray_menu
-            .set_intercept_enter(new_config.intercept_enter);
-        self.tray_menu
-            .set_whitelist_enabled(new_config.enable_whitelist);
+        // 仅同步发生变化的部分，避免每次保存都重新注册快捷键
+        if changes.hotkeys {
+            self.hotkey_manager.update(new_config);
+        }
+        if changes.hotkey_profiles {
+            self.tray_menu.set_hotkey_profiles(new_config);
+        }
+        if changes.profiles {
+            self.tray_menu.set_profiles(new_config);
+        }
+        if changes.process_mode {
+            self.tray_menu.set_process_mode(new_config.process_mode);
+        }
+        if changes.intercept_enter {
+            self.tray_menu
+                .set_intercept_enter(self.keyboard_hook && new_config.intercept_enter);
+        }
+        if changes.enable_whitelist {
+            self.tray_menu
+                .set_whitelist_enabled(new_config.enable_whitelist);
+        }
 
         let current_character = new_config.current_character.clone();
         drop(config_manager);
 
+        if !changes.current_character {
+            return;
+        }
+
         if let Some(character_data) = self.data_manager.get_character(&current_character) {
             let character_name = character_data.name.clone();
             self.tray_menu.update_tooltip(&character_name);
@@ -146,15 +189,62 @@ impl App {
         if event.id == self.hotkey_manager.toggle_hotkey.id() {
             self.handle_message(ControlMessage::ToggleIntercept);
         } else if event.id == self.hotkey_manager.generate_hotkey.id() {
-            let (should_process, process_mode) = {
-                let config_manager = self.config_manager.read().unwrap();
-                let config = config_manager.get_config();
-                (check_whitelist(config), config.process_mode)
-            };
-
-            if should_process {
-                self.process_image_in_thread(process_mode, false);
-            }
+            self.generate_from_hotkey();
+        } else if self
+            .hotkey_manager
+            .next_character_hotkey
+            .is_some_and(|h| h.id() == event.id)
+        {
+            self.cycle_character(1);
+        } else if self
+            .hotkey_manager
+            .prev_character_hotkey
+            .is_some_and(|h| h.id() == event.id)
+        {
+            self.cycle_character(-1);
+        } else if let Some(character) = self.hotkey_manager.character_for(event.id) {
+            // 角色快捷键先切换到该角色，再按生成快捷键的方式处理
+            let character = character.to_string();
+            self.handle_message(ControlMessage::SwitchCharacter(character));
+            self.generate_from_hotkey();
+        }
+    }
+
+    /// 按角色 ID 排序后循环切换到下一个(`step` 为 1)或上一个(`step` 为 -1)角色
+    fn cycle_character(&mut self, step: isize) {
+        let mut ids = self
+            .data_manager
+            .get_characters()
+            .iter()
+            .map(|c| c.id.clone())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        let current = self
+            .config_manager
+            .read()
+            .unwrap()
+            .get_config()
+            .current_character
+            .clone();
+        let next = match ids.iter().position(|id| *id == current) {
+            Some(index) => (index as isize + step).rem_euclid(ids.len() as isize) as usize,
+            None => 0,
+        };
+        if let Some(id) = ids.into_iter().nth(next) {
+            self.handle_message(ControlMessage::SwitchCharacter(id));
+        }
+    }
+
+    fn generate_from_hotkey(&self) {
+        let (should_process, process_mode) = {
+            let config_manager = self.config_manager.read().unwrap();
+            let config = config_manager.get_config();
+            (check_whitelist(config), config.process_mode)
+        };
+
+        if should_process {
+            self.process_image_in_thread(process_mode, false);
         }
     }
 
@@ -169,6 +259,23 @@ impl App {
                     config_manager.set_current_character(id.to_string()).ok();
                 }
             }
+            ControlMessage::SwitchHotkeyProfile(profile) => {
+                let old_config = {
+                    let mut config_manager = self.config_manager.write().unwrap();
+                    let old_config = config_manager.get_config().clone();
+                    config_manager.set_active_hotkey_profile(profile).ok();
+                    old_config
+                };
+                self.handle_reload_config(&old_config);
+            }
+            ControlMessage::SwitchProfile(name) => {
+                let old_config = self
+                    .config_manager
+                    .write()
+                    .unwrap()
+                    .set_active_profile(name);
+                self.handle_reload_config(&old_config);
+            }
             ControlMessage::ToggleAutoPaste => {
                 let mut config_manager = self.config_manager.write().unwrap();
                 let new_mode = match config_manager.get_config().process_mode {
@@ -189,10 +296,12 @@ impl App {
 
                 self.tray_menu.set_process_mode(new_mode);
             }
+            ControlMessage::ToggleIntercept if !self.keyboard_hook => {}
             ControlMessage::ToggleIntercept => {
                 let mut config_manager = self.config_manager.write().unwrap();
                 let new_enabled = !config_manager.get_config().intercept_enter;
                 config_manager.set_intercept_enter(new_enabled).ok();
+                reset_modifier_keys();
 
                 self.tray_menu.set_intercept_enter(new_enabled);
             }
@@ -203,6 +312,27 @@ impl App {
 
                 self.tray_menu.set_whitelist_enabled(new_enabled);
             }
+            ControlMessage::ResetConfig => {
+                let confirmed = MessageDialog::new()
+                    .set_level(MessageLevel::Warning)
+                    .set_title("重置设置")
+                    .set_description("确定要将所有设置恢复为默认值吗？当前角色将被保留。")
+                    .set_buttons(MessageButtons::YesNo)
+                    .show()
+                    == MessageDialogResult::Yes;
+
+                if confirmed {
+                    let old_config = self.config_manager.write().unwrap().reset();
+                    self.handle_reload_config(&old_config);
+                }
+            }
+            ControlMessage::ShowStats => {
+                let summary = self.stats.lock().unwrap().summary();
+                MessageDialog::new()
+                    .set_title("性能统计")
+                    .set_description(summary)
+                    .show();
+            }
             ControlMessage::Help => {
                 open::that("https://github.com/USTC-XeF2/imagebox").ok();
             }
@@ -222,12 +352,23 @@ impl App {
 
         let is_processing_clone = self.is_processing.clone();
         let data_manager = self.data_manager.clone();
+        let last_used = self.last_used.clone();
+        let stats = self.stats.clone();
         let config = self.config_manager.read().unwrap().get_config().clone();
 
         drop(processing);
 
         thread::spawn(move || {
-            process_image(&config, &data_manager, process_mode, enable_max_chars);
+            process_image(
+                &config,
+                &data_manager,
+                &last_used,
+                &stats,
+                process_mode,
+                enable_max_chars,
+            );
+            // 模拟按键期间可能漏掉修饰键的松开事件，处理完成后清除以免拦截失效
+            reset_modifier_keys();
 
             if let Ok(mut processing) = is_processing_clone.lock() {
                 *processing = false;
@@ -255,8 +396,8 @@ impl ApplicationHandler<UserEvent> for App {
                 }
             }
             UserEvent::HotkeyEvent(hotkey_event) => self.handle_hotkey_event(hotkey_event),
-            UserEvent::EnterKeyPressed => {
-                self.process_image_in_thread(ProcessMode::Send, true);
+            UserEvent::EnterKeyPressed(process_mode) => {
+                self.process_image_in_thread(process_mode, true);
             }
         }
     }
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::mem;
 use std::path::{Path, PathBuf};
@@ -6,9 +7,11 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use anyhow::Result;
+use glob::{MatchOptions, Pattern};
 use global_hotkey::hotkey::{Code, HotKey, Modifiers};
 use notify_debouncer_full::notify::{RecommendedWatcher, RecursiveMode};
 use notify_debouncer_full::{DebounceEventResult, Debouncer, RecommendedCache, new_debouncer};
+use rdev::Key;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
@@ -20,6 +23,155 @@ pub enum ProcessMode {
     Send,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImeMode {
+    #[default]
+    Auto,
+    Off,
+    AlwaysPass,
+}
+
+/// `auto` 模式下无法检测输入法状态时的处理方式，取值含义与 `ImeMode` 相同
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImeFallback {
+    #[default]
+    Off,
+    AlwaysPass,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyMode {
+    #[default]
+    None,
+    Sound,
+    Balloon,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureMode {
+    #[default]
+    All,
+    CurrentLine,
+    /// 不模拟选择，直接复制已选中的文字
+    Selection,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextSource {
+    #[default]
+    None,
+    Clipboard,
+    Fixed,
+}
+
+/// 应用程序白名单，含有 `*`、`?` 或 `[` 的条目按不区分大小写的 glob 模式匹配，其余条目按名称精确匹配
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(from = "Vec<String>", into = "Vec<String>")]
+pub struct Whitelist {
+    entries: Vec<String>,
+    patterns: Vec<Pattern>,
+}
+
+impl From<Vec<String>> for Whitelist {
+    fn from(entries: Vec<String>) -> Self {
+        let patterns = entries
+            .iter()
+            .filter(|entry| entry.contains(['*', '?', '[']))
+            .filter_map(|entry| match Pattern::new(entry) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    eprintln!("白名单模式 '{}' 无效，按名称精确匹配：{}", entry, e);
+                    None
+                }
+            })
+            .collect();
+        Whitelist { entries, patterns }
+    }
+}
+
+impl From<Whitelist> for Vec<String> {
+    fn from(whitelist: Whitelist) -> Self {
+        whitelist.entries
+    }
+}
+
+impl PartialEq for Whitelist {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl Whitelist {
+    fn matches_pattern(&self, text: &str) -> bool {
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.matches_with(text, options))
+    }
+
+    pub fn matches_app_name(&self, app_name: &str) -> bool {
+        self.entries.iter().any(|entry| entry == app_name) || self.matches_pattern(app_name)
+    }
+
+    /// 窗口标题包含普通条目即视为匹配，模式条目需匹配整个标题
+    pub fn matches_title(&self, title: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| !entry.is_empty() && title.contains(entry.as_str()))
+            || self.matches_pattern(title)
+    }
+}
+
+/// 白名单匹配的对象
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WhitelistTarget {
+    #[default]
+    AppName,
+    Title,
+    Either,
+}
+
+/// 一组可整体切换的快捷键
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct HotkeyProfile {
+    #[serde(default = "default_toggle_hotkey")]
+    pub toggle_hotkey: HotKey,
+    #[serde(default = "default_generate_hotkey")]
+    pub generate_hotkey: HotKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_character_hotkey: Option<HotKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_character_hotkey: Option<HotKey>,
+}
+
+/// 配置方案，切换时将设置的项覆盖到顶层配置，缺省的项保持不变
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConfigProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_character: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_mode: Option<ProcessMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intercept_enter: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enable_whitelist: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist: Option<Whitelist>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist_target: Option<WhitelistTarget>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_hotkey_profile: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Config {
     #[serde(default)]
@@ -28,31 +180,94 @@ pub struct Config {
     pub process_mode: ProcessMode,
     #[serde(default)]
     pub intercept_enter: bool,
+    #[serde(default = "default_trigger_key")]
+    pub trigger_key: Key,
+    #[serde(default = "default_trigger_process_mode")]
+    pub trigger_process_mode: ProcessMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modifier_trigger: Option<String>,
+    #[serde(default = "default_trigger_process_mode")]
+    pub modifier_trigger_mode: ProcessMode,
+    #[serde(default)]
+    pub capture_mode: CaptureMode,
+    #[serde(default = "default_line_start_key")]
+    pub line_start_key: String,
+    #[serde(default = "default_line_end_key")]
+    pub line_end_key: String,
+    #[serde(default)]
+    pub auto_send_on_complete: bool,
+    #[serde(default = "default_terminal_punctuation")]
+    pub terminal_punctuation: String,
+    #[serde(default)]
+    pub ime_mode: ImeMode,
+    #[serde(default)]
+    pub ime_fallback: ImeFallback,
+    #[serde(default)]
+    pub disable_keyboard_hook: bool,
     #[serde(default = "default_enable_whitelist")]
     pub enable_whitelist: bool,
     #[serde(default = "default_whitelist")]
-    pub whitelist: Vec<String>,
+    pub whitelist: Whitelist,
+    #[serde(default)]
+    pub whitelist_target: WhitelistTarget,
     #[serde(default = "default_max_image_size")]
     pub max_image_size: usize,
     #[serde(default = "default_max_chars")]
     pub max_chars: usize,
+    #[serde(default = "default_sanitize_text")]
+    pub sanitize_text: bool,
+    #[serde(default)]
+    pub context_source: ContextSource,
+    #[serde(default)]
+    pub context_text: String,
+    #[serde(default)]
+    pub restore_clipboard: bool,
+    #[serde(default = "default_restore_clipboard_delay_ms")]
+    pub restore_clipboard_delay_ms: u64,
+    #[serde(default = "default_key_delay_ms")]
+    pub key_delay_ms: u64,
+    #[serde(default = "default_select_delay_ms")]
+    pub select_delay_ms: u64,
+    #[serde(default = "default_copy_delay_ms")]
+    pub copy_delay_ms: u64,
+    #[serde(default = "default_paste_delay_ms")]
+    pub paste_delay_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_fallback: Option<String>,
+    #[serde(default)]
+    pub notify_on_send: NotifyMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_sound: Option<String>,
     #[serde(default = "default_toggle_hotkey")]
     pub toggle_hotkey: HotKey,
     #[serde(default = "default_generate_hotkey")]
     pub generate_hotkey: HotKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_character_hotkey: Option<HotKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_character_hotkey: Option<HotKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_hotkey_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hotkey_profiles: BTreeMap<String, HotkeyProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ConfigProfile>,
 }
 
 fn default_enable_whitelist() -> bool {
     true
 }
 
-fn default_whitelist() -> Vec<String> {
+fn default_whitelist() -> Whitelist {
     vec![
         "WeChat".to_string(),
         "Weixin".to_string(),
         "QQ".to_string(),
         "TIM".to_string(),
     ]
+    .into()
 }
 
 fn default_max_image_size() -> usize {
@@ -63,6 +278,58 @@ fn default_max_chars() -> usize {
     50
 }
 
+fn default_sanitize_text() -> bool {
+    true
+}
+
+fn default_restore_clipboard_delay_ms() -> u64 {
+    500
+}
+
+fn default_key_delay_ms() -> u64 {
+    5
+}
+
+fn default_select_delay_ms() -> u64 {
+    20
+}
+
+fn default_copy_delay_ms() -> u64 {
+    30
+}
+
+fn default_paste_delay_ms() -> u64 {
+    100
+}
+
+fn default_line_start_key() -> String {
+    if cfg!(target_os = "macos") {
+        "Meta+Left".to_string()
+    } else {
+        "Home".to_string()
+    }
+}
+
+fn default_line_end_key() -> String {
+    if cfg!(target_os = "macos") {
+        "Meta+Right".to_string()
+    } else {
+        "End".to_string()
+    }
+}
+
+fn default_terminal_punctuation() -> String {
+    "。！？…～.!?~".to_string()
+}
+
+fn default_trigger_key() -> Key {
+    Key::Return
+}
+
+fn default_trigger_process_mode() -> ProcessMode {
+    ProcessMode::Send
+}
+
 fn default_toggle_hotkey() -> HotKey {
     HotKey::new(Some(Modifiers::CONTROL | Modifiers::ALT), Code::KeyT)
 }
@@ -77,12 +344,43 @@ impl Default for Config {
             current_character: String::new(),
             process_mode: ProcessMode::default(),
             intercept_enter: false,
+            trigger_key: default_trigger_key(),
+            trigger_process_mode: default_trigger_process_mode(),
+            modifier_trigger: None,
+            modifier_trigger_mode: default_trigger_process_mode(),
+            capture_mode: CaptureMode::default(),
+            line_start_key: default_line_start_key(),
+            line_end_key: default_line_end_key(),
+            auto_send_on_complete: false,
+            terminal_punctuation: default_terminal_punctuation(),
+            ime_mode: ImeMode::default(),
+            ime_fallback: ImeFallback::default(),
+            disable_keyboard_hook: false,
             enable_whitelist: true,
             whitelist: default_whitelist(),
+            whitelist_target: WhitelistTarget::default(),
             max_image_size: default_max_image_size(),
             max_chars: default_max_chars(),
+            sanitize_text: default_sanitize_text(),
+            context_source: ContextSource::default(),
+            context_text: String::new(),
+            restore_clipboard: false,
+            restore_clipboard_delay_ms: default_restore_clipboard_delay_ms(),
+            key_delay_ms: default_key_delay_ms(),
+            select_delay_ms: default_select_delay_ms(),
+            copy_delay_ms: default_copy_delay_ms(),
+            paste_delay_ms: default_paste_delay_ms(),
+            cooldown_fallback: None,
+            notify_on_send: NotifyMode::default(),
+            notify_sound: None,
             toggle_hotkey: default_toggle_hotkey(),
             generate_hotkey: default_generate_hotkey(),
+            next_character_hotkey: None,
+            prev_character_hotkey: None,
+            active_hotkey_profile: None,
+            hotkey_profiles: BTreeMap::new(),
+            active_profile: None,
+            profiles: BTreeMap::new(),
         }
     }
 }
@@ -103,6 +401,47 @@ impl Config {
         fs::write(path, content)?;
         Ok(())
     }
+
+    /// 当前生效的快捷键，未选择方案或方案不存在时使用顶层的快捷键配置
+    pub fn active_hotkeys(&self) -> HotkeyProfile {
+        self.active_hotkey_profile
+            .as_ref()
+            .and_then(|name| self.hotkey_profiles.get(name))
+            .copied()
+            .unwrap_or(HotkeyProfile {
+                toggle_hotkey: self.toggle_hotkey,
+                generate_hotkey: self.generate_hotkey,
+                next_character_hotkey: self.next_character_hotkey,
+                prev_character_hotkey: self.prev_character_hotkey,
+            })
+    }
+}
+
+/// 重载前后配置中需要同步到界面与快捷键的变化
+pub struct ConfigChanges {
+    pub hotkeys: bool,
+    pub hotkey_profiles: bool,
+    pub profiles: bool,
+    pub process_mode: bool,
+    pub intercept_enter: bool,
+    pub enable_whitelist: bool,
+    pub current_character: bool,
+}
+
+impl ConfigChanges {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        ConfigChanges {
+            hotkeys: old.active_hotkeys() != new.active_hotkeys(),
+            hotkey_profiles: old.active_hotkey_profile != new.active_hotkey_profile
+                || !old.hotkey_profiles.keys().eq(new.hotkey_profiles.keys()),
+            profiles: old.active_profile != new.active_profile
+                || !old.profiles.keys().eq(new.profiles.keys()),
+            process_mode: old.process_mode != new.process_mode,
+            intercept_enter: old.intercept_enter != new.intercept_enter,
+            enable_whitelist: old.enable_whitelist != new.enable_whitelist,
+            current_character: old.current_character != new.current_character,
+        }
+    }
 }
 
 pub struct ConfigManager {
@@ -173,6 +512,17 @@ impl ConfigManager {
         old_config
     }
 
+    pub fn reset(&mut self) -> Config {
+        let default_config = Config {
+            current_character: self.config.current_character.clone(),
+            ..Config::default()
+        };
+        let old_config = mem::replace(&mut self.config, default_config);
+        self.save_config().ok();
+
+        old_config
+    }
+
     pub fn set_current_character(&mut self, character: String) -> Result<()> {
         self.config.current_character = character;
         self.save_config()
@@ -192,4 +542,84 @@ impl ConfigManager {
         self.config.enable_whitelist = enabled;
         self.save_config()
     }
+
+    pub fn set_active_hotkey_profile(&mut self, profile: Option<String>) -> Result<()> {
+        self.config.active_hotkey_profile = profile;
+        self.save_config()
+    }
+
+    /// 切换到指定的配置方案并保存，返回切换前的配置，方案不存在时不做修改
+    pub fn set_active_profile(&mut self, name: String) -> Config {
+        let old_config = self.config.clone();
+        let Some(profile) = self.config.profiles.get(&name).cloned() else {
+            return old_config;
+        };
+
+        let config = &mut self.config;
+        if let Some(character) = profile.current_character {
+            config.current_character = character;
+        }
+        if let Some(mode) = profile.process_mode {
+            config.process_mode = mode;
+        }
+        if let Some(enabled) = profile.intercept_enter {
+            config.intercept_enter = enabled;
+        }
+        if let Some(enabled) = profile.enable_whitelist {
+            config.enable_whitelist = enabled;
+        }
+        if let Some(whitelist) = profile.whitelist {
+            config.whitelist = whitelist;
+        }
+        if let Some(target) = profile.whitelist_target {
+            config.whitelist_target = target;
+        }
+        if profile.active_hotkey_profile.is_some() {
+            config.active_hotkey_profile = profile.active_hotkey_profile;
+        }
+        config.active_profile = Some(name);
+        self.save_config().ok();
+
+        old_config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unrelated_change_does_not_rescan_or_reregister() {
+        let old = Config::default();
+        let new = Config {
+            max_chars: old.max_chars + 1,
+            key_delay_ms: old.key_delay_ms + 1,
+            ..old.clone()
+        };
+
+        let changes = ConfigChanges::between(&old, &new);
+        assert!(!changes.current_character);
+        assert!(!changes.hotkeys);
+        assert!(!changes.hotkey_profiles);
+        assert!(!changes.profiles);
+        assert!(!changes.process_mode);
+        assert!(!changes.intercept_enter);
+        assert!(!changes.enable_whitelist);
+    }
+
+    #[test]
+    fn only_changed_fields_are_reported() {
+        let old = Config::default();
+        let new = Config {
+            current_character: "other".to_string(),
+            generate_hotkey: HotKey::new(Some(Modifiers::CONTROL), Code::KeyG),
+            ..old.clone()
+        };
+
+        let changes = ConfigChanges::between(&old, &new);
+        assert!(changes.current_character);
+        assert!(changes.hotkeys);
+        assert!(!changes.process_mode);
+        assert!(!changes.intercept_enter);
+    }
 }
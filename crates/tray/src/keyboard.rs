@@ -1,4 +1,4 @@
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 
@@ -6,15 +6,17 @@ use active_win_pos_rs::get_active_window;
 use anyhow::Result;
 use global_hotkey::GlobalHotKeyManager;
 use global_hotkey::hotkey::HotKey;
+use imagebox_core::CharacterConfig;
 use rdev::{Event, EventType, Key, grab};
 
-use crate::config::{Config, ConfigManager};
+use crate::config::{Config, ConfigManager, ImeFallback, ImeMode, ProcessMode, WhitelistTarget};
 
 const SHIFT_MASK: u8 = 0b001;
 const CTRL_MASK: u8 = 0b010;
 const ALT_MASK: u8 = 0b100;
 
 static MODIFIER_KEYS: AtomicU8 = AtomicU8::new(0);
+static TYPED_SINCE_ENTER: AtomicBool = AtomicBool::new(false);
 
 fn set_modifier_key(mask: u8, pressed: bool) {
     if pressed {
@@ -24,13 +26,187 @@ fn set_modifier_key(mask: u8, pressed: bool) {
     }
 }
 
+/// 清除记录的修饰键状态，用于在漏掉松开事件后恢复拦截
+pub fn reset_modifier_keys() {
+    MODIFIER_KEYS.store(0, Ordering::Relaxed);
+}
+
+/// 获取当前按下的修饰键，可以查询真实按键状态时以其修正记录的状态
+#[cfg(windows)]
+fn current_modifier_keys() -> u8 {
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+        GetAsyncKeyState, VK_CONTROL, VK_MENU, VK_SHIFT,
+    };
+
+    let pressed = |key: u16| unsafe { GetAsyncKeyState(key as i32) } < 0;
+    let mut mask = 0;
+    if pressed(VK_SHIFT) {
+        mask |= SHIFT_MASK;
+    }
+    if pressed(VK_CONTROL) {
+        mask |= CTRL_MASK;
+    }
+    if pressed(VK_MENU) {
+        mask |= ALT_MASK;
+    }
+
+    MODIFIER_KEYS.store(mask, Ordering::Relaxed);
+    mask
+}
+
+/// 获取当前按下的修饰键，可以查询真实按键状态时以其修正记录的状态
+#[cfg(not(windows))]
+fn current_modifier_keys() -> u8 {
+    MODIFIER_KEYS.load(Ordering::Relaxed)
+}
+
+/// 解析形如 `Ctrl+Shift` 的修饰键组合，返回对应的掩码
+fn parse_modifier_mask(combo: &str) -> Option<u8> {
+    combo
+        .split('+')
+        .map(str::trim)
+        .try_fold(0, |mask, modifier| {
+            let bit = match modifier {
+                "Ctrl" => CTRL_MASK,
+                "Shift" => SHIFT_MASK,
+                "Alt" => ALT_MASK,
+                _ => return None,
+            };
+            Some(mask | bit)
+        })
+}
+
+/// 根据当前按下的修饰键决定触发时的处理模式，不应触发时返回 `None`
+fn trigger_process_mode(config: &Config, modifiers: u8) -> Option<ProcessMode> {
+    if modifiers == 0 {
+        return Some(config.trigger_process_mode);
+    }
+
+    let combo = config.modifier_trigger.as_deref()?;
+    match parse_modifier_mask(combo) {
+        Some(mask) if mask == modifiers => Some(config.modifier_trigger_mode),
+        Some(_) => None,
+        None => {
+            eprintln!("无效的修饰键组合：{}", combo);
+            None
+        }
+    }
+}
+
+/// 查询输入法是否处于组合输入状态，无法获取时返回 `None`
+///
+/// 其他进程的窗口无法取得输入法上下文，此时通过默认输入法窗口查询开关状态与转换模式：
+/// 输入法关闭或处于英数模式时一定不在组合输入，否则无法判断
+#[cfg(windows)]
+fn is_ime_composing() -> Option<bool> {
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::HWND;
+    use windows_sys::Win32::UI::Input::Ime::{
+        GCS_COMPSTR, IME_CMODE_NATIVE, ImmGetCompositionStringW, ImmGetContext,
+        ImmGetDefaultIMEWnd, ImmReleaseContext,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GUITHREADINFO, GetForegroundWindow, GetGUIThreadInfo, GetWindowThreadProcessId,
+        SMTO_ABORTIFHUNG, SendMessageTimeoutW, WM_IME_CONTROL,
+    };
+
+    const IMC_GETCONVERSIONMODE: usize = 0x0001;
+    const IMC_GETOPENSTATUS: usize = 0x0005;
+    // 在键盘钩子中调用，超时过长会导致系统跳过钩子
+    const IME_QUERY_TIMEOUT_MS: u32 = 50;
+
+    let query_ime_window = |ime_window: HWND, command: usize| {
+        let mut result: usize = 0;
+        let sent = unsafe {
+            SendMessageTimeoutW(
+                ime_window,
+                WM_IME_CONTROL,
+                command,
+                0,
+                SMTO_ABORTIFHUNG,
+                IME_QUERY_TIMEOUT_MS,
+                &mut result,
+            )
+        };
+        (sent != 0).then_some(result)
+    };
+
+    unsafe {
+        let foreground = GetForegroundWindow();
+        if foreground.is_null() {
+            return None;
+        }
+
+        // 输入法上下文绑定在拥有焦点的子窗口上，取不到时退回前台窗口
+        let thread_id = GetWindowThreadProcessId(foreground, ptr::null_mut());
+        let mut info = GUITHREADINFO {
+            cbSize: size_of::<GUITHREADINFO>() as u32,
+            ..Default::default()
+        };
+        let window = if GetGUIThreadInfo(thread_id, &mut info) != 0 && !info.hwndFocus.is_null() {
+            info.hwndFocus
+        } else {
+            foreground
+        };
+
+        let context = ImmGetContext(window);
+        if !context.is_null() {
+            let length = ImmGetCompositionStringW(context, GCS_COMPSTR, ptr::null_mut(), 0);
+            ImmReleaseContext(window, context);
+            return Some(length > 0);
+        }
+
+        let ime_window = ImmGetDefaultIMEWnd(window);
+        if ime_window.is_null() {
+            return None;
+        }
+        if query_ime_window(ime_window, IMC_GETOPENSTATUS)? == 0 {
+            return Some(false);
+        }
+        match query_ime_window(ime_window, IMC_GETCONVERSIONMODE) {
+            Some(mode) if mode as u32 & IME_CMODE_NATIVE == 0 => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// 查询输入法是否处于组合输入状态，无法获取时返回 `None`
+#[cfg(not(windows))]
+fn is_ime_composing() -> Option<bool> {
+    None
+}
+
+/// 判断 Enter 是否应交给输入法上屏而不触发生成
+fn should_pass_to_ime(mode: ImeMode, fallback: ImeFallback) -> bool {
+    let typed = TYPED_SINCE_ENTER.swap(false, Ordering::Relaxed);
+    match mode {
+        ImeMode::Auto => is_ime_composing().unwrap_or(match fallback {
+            ImeFallback::Off => false,
+            ImeFallback::AlwaysPass => typed,
+        }),
+        ImeMode::Off => false,
+        ImeMode::AlwaysPass => typed,
+    }
+}
+
 pub fn check_whitelist(config: &Config) -> bool {
     if !config.enable_whitelist {
         return true;
     }
 
     match get_active_window() {
-        Ok(active_window) => config.whitelist.contains(&active_window.app_name),
+        Ok(active_window) => {
+            let whitelist = &config.whitelist;
+            match config.whitelist_target {
+                WhitelistTarget::AppName => whitelist.matches_app_name(&active_window.app_name),
+                WhitelistTarget::Title => whitelist.matches_title(&active_window.title),
+                WhitelistTarget::Either => {
+                    whitelist.matches_app_name(&active_window.app_name)
+                        || whitelist.matches_title(&active_window.title)
+                }
+            }
+        }
         Err(()) => false,
     }
 }
@@ -40,33 +216,80 @@ pub struct HotkeyManager {
 
     pub toggle_hotkey: HotKey,
     pub generate_hotkey: HotKey,
+    pub next_character_hotkey: Option<HotKey>,
+    pub prev_character_hotkey: Option<HotKey>,
+    /// 各角色的专属快捷键与对应的角色 ID
+    character_hotkeys: Vec<(HotKey, String)>,
 }
 
 impl HotkeyManager {
-    pub fn new(config: &Config) -> Result<Self> {
-        let manager = GlobalHotKeyManager::new()?;
-        let toggle_hotkey = config.toggle_hotkey;
-        let generate_hotkey = config.generate_hotkey;
-
-        manager.register(toggle_hotkey).ok();
-        manager.register(generate_hotkey).ok();
-
-        Ok(Self {
-            manager,
-            toggle_hotkey,
-            generate_hotkey,
-        })
+    pub fn new(config: &Config, characters: &[CharacterConfig]) -> Result<Self> {
+        let hotkeys = config.active_hotkeys();
+        let character_hotkeys = characters
+            .iter()
+            .filter_map(|character| {
+                let hotkey = character.hotkey.as_ref()?;
+                match hotkey.parse::<HotKey>() {
+                    Ok(parsed) => Some((parsed, character.id.clone())),
+                    Err(e) => {
+                        eprintln!("角色 '{}' 的快捷键 {} 无效：{}", character.id, hotkey, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let hotkey_manager = Self {
+            manager: GlobalHotKeyManager::new()?,
+            toggle_hotkey: hotkeys.toggle_hotkey,
+            generate_hotkey: hotkeys.generate_hotkey,
+            next_character_hotkey: hotkeys.next_character_hotkey,
+            prev_character_hotkey: hotkeys.prev_character_hotkey,
+            character_hotkeys,
+        };
+        hotkey_manager.register_all();
+
+        Ok(hotkey_manager)
     }
 
+    /// 查找快捷键 ID 对应的角色
+    pub fn character_for(&self, id: u32) -> Option<&str> {
+        self.character_hotkeys
+            .iter()
+            .find(|(hotkey, _)| hotkey.id() == id)
+            .map(|(_, character)| character.as_str())
+    }
+
+    fn all_hotkeys(&self) -> impl Iterator<Item = HotKey> + '_ {
+        [self.toggle_hotkey, self.generate_hotkey]
+            .into_iter()
+            .chain(self.next_character_hotkey)
+            .chain(self.prev_character_hotkey)
+            .chain(self.character_hotkeys.iter().map(|(hotkey, _)| *hotkey))
+    }
+
+    /// 注销当前的全部快捷键后注册新配置中生效的快捷键，切换快捷键方案时不会残留旧方案的绑定
     pub fn update(&mut self, config: &Config) {
-        self.manager.unregister(self.toggle_hotkey).ok();
-        self.manager.unregister(self.generate_hotkey).ok();
+        for hotkey in self.all_hotkeys() {
+            if let Err(e) = self.manager.unregister(hotkey) {
+                eprintln!("注销快捷键 {} 失败：{}", hotkey, e);
+            }
+        }
+
+        let hotkeys = config.active_hotkeys();
+        self.toggle_hotkey = hotkeys.toggle_hotkey;
+        self.generate_hotkey = hotkeys.generate_hotkey;
+        self.next_character_hotkey = hotkeys.next_character_hotkey;
+        self.prev_character_hotkey = hotkeys.prev_character_hotkey;
 
-        self.toggle_hotkey = config.toggle_hotkey;
-        self.generate_hotkey = config.generate_hotkey;
+        self.register_all();
+    }
 
-        self.manager.register(self.toggle_hotkey).ok();
-        self.manager.register(self.generate_hotkey).ok();
+    fn register_all(&self) {
+        for hotkey in self.all_hotkeys() {
+            if let Err(e) = self.manager.register(hotkey) {
+                eprintln!("注册快捷键 {} 失败：{}", hotkey, e);
+            }
+        }
     }
 }
 
@@ -76,7 +299,7 @@ pub fn start_keyboard_listener<F>(
     on_enter: F,
 ) -> thread::JoinHandle<()>
 where
-    F: Fn() + Send + 'static,
+    F: Fn(ProcessMode) + Send + 'static,
 {
     thread::spawn(move || {
         if let Err(error) =
@@ -87,6 +310,10 @@ where
     })
 }
 
+fn is_trigger_key(key: Key, config_manager: &RwLock<ConfigManager>) -> bool {
+    config_manager.read().unwrap().get_config().trigger_key == key
+}
+
 fn handle_enter_key<F>(
     event: Event,
     is_processing: &Mutex<bool>,
@@ -94,7 +321,7 @@ fn handle_enter_key<F>(
     on_enter: &F,
 ) -> Option<Event>
 where
-    F: Fn(),
+    F: Fn(ProcessMode),
 {
     match event.event_type {
         EventType::KeyPress(Key::ShiftLeft | Key::ShiftRight) => {
@@ -116,17 +343,21 @@ where
             set_modifier_key(ALT_MASK, false);
         }
 
-        EventType::KeyPress(Key::Return) => {
-            if MODIFIER_KEYS.load(Ordering::Relaxed) != 0 {
-                return Some(event);
-            }
-
-            {
+        EventType::KeyPress(key) if is_trigger_key(key, config_manager) => {
+            let process_mode = {
                 let config_manager_guard = config_manager.read().unwrap();
                 let config = config_manager_guard.get_config();
-                if !config.intercept_enter || !check_whitelist(config) {
+                let Some(process_mode) = trigger_process_mode(config, current_modifier_keys())
+                else {
+                    return Some(event);
+                };
+                if !config.intercept_enter
+                    || !check_whitelist(config)
+                    || should_pass_to_ime(config.ime_mode, config.ime_fallback)
+                {
                     return Some(event);
                 }
+                process_mode
             };
 
             let processing = is_processing.lock().unwrap();
@@ -136,9 +367,12 @@ where
             }
             drop(processing);
 
-            on_enter();
+            on_enter(process_mode);
             return None;
         }
+        EventType::KeyPress(_) => {
+            TYPED_SINCE_ENTER.store(true, Ordering::Relaxed);
+        }
         _ => {}
     }
 
@@ -3,7 +3,9 @@
 mod app;
 mod config;
 mod keyboard;
+mod notification;
 mod processor;
+mod stats;
 mod tray;
 
 use std::path::PathBuf;
@@ -37,7 +39,8 @@ fn main() -> Result<()> {
         let current_dir = get_current_dir();
 
         let event_loop = EventLoop::<UserEvent>::with_user_event().build().unwrap();
-        let mut app = App::new(&current_dir, &event_loop)?;
+        let no_intercept = std::env::args().any(|arg| arg == "--no-intercept");
+        let mut app = App::new(&current_dir, &event_loop, no_intercept)?;
 
         event_loop.run_app(&mut app)?;
     }
@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::thread;
+
+use anyhow::{Result, anyhow};
+use notify_rust::Notification;
+use rodio::OutputStreamBuilder;
+
+use crate::config::{Config, NotifyMode};
+use crate::get_current_dir;
+
+fn play_sound(path: &Path) -> Result<()> {
+    let mut stream = OutputStreamBuilder::open_default_stream()?;
+    stream.log_on_drop(false);
+
+    let file = File::open(path)?;
+    let sink = rodio::play(stream.mixer(), BufReader::new(file))?;
+    sink.sleep_until_end();
+    Ok(())
+}
+
+fn show_balloon(body: &str) -> Result<()> {
+    Notification::new()
+        .appname("ImageBox")
+        .summary("ImageBox")
+        .body(body)
+        .show()?;
+    Ok(())
+}
+
+/// 角色冷却中跳过生成时总是弹出通知，否则用户无从得知按键没有生效
+pub fn notify_cooldown(character_name: &str) {
+    let body = format!("{} 冷却中，已跳过生成", character_name);
+    thread::spawn(move || {
+        if let Err(e) = show_balloon(&body) {
+            eprintln!("冷却提醒失败：{}", e);
+        }
+    });
+}
+
+/// 在后台线程中发出生成完成的提醒，不阻塞粘贴与发送
+pub fn notify_generated(config: &Config, character_name: &str) {
+    let mode = config.notify_on_send;
+    if mode == NotifyMode::None {
+        return;
+    }
+
+    let sound_path = config
+        .notify_sound
+        .as_ref()
+        .map(|path| get_current_dir().join(path));
+    let character_name = character_name.to_string();
+
+    thread::spawn(move || {
+        let result = match mode {
+            NotifyMode::None => Ok(()),
+            NotifyMode::Sound => sound_path
+                .ok_or_else(|| anyhow!("未配置 notify_sound"))
+                .and_then(|path| play_sound(&path)),
+            NotifyMode::Balloon => show_balloon(&format!("已生成 {} 的图片", character_name)),
+        };
+
+        if let Err(e) = result {
+            eprintln!("生成提醒失败：{}", e);
+        }
+    });
+}
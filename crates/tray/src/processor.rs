@@ -1,71 +1,389 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use arboard::{Clipboard, ImageData};
 use rdev::{EventType, Key, simulate};
 
-use imagebox_core::{DataManager, generate_image};
+use imagebox_core::{
+    CharacterConfig, DataManager, EncodeOptions, compress_character_image, generate_image,
+};
 
-use crate::config::{Config, ProcessMode};
+use crate::config::{CaptureMode, Config, ContextSource, ProcessMode};
+use crate::notification::{notify_cooldown, notify_generated};
+use crate::stats::{Stats, Timing};
 
-fn send_key(event_type: &EventType) {
+fn send_key(event_type: &EventType, delay: Duration) {
     simulate(event_type).ok();
-    thread::sleep(Duration::from_millis(5));
+    thread::sleep(delay);
 }
 
-fn simulate_key_combo(key: Key) {
+fn simulate_key_combo(key: Key, delay: Duration) {
     let modifier = if cfg!(target_os = "macos") {
         Key::MetaLeft
     } else {
         Key::ControlLeft
     };
 
-    send_key(&EventType::KeyPress(modifier));
-    send_key(&EventType::KeyPress(key));
-    send_key(&EventType::KeyRelease(key));
-    send_key(&EventType::KeyRelease(modifier));
+    send_key(&EventType::KeyPress(modifier), delay);
+    send_key(&EventType::KeyPress(key), delay);
+    send_key(&EventType::KeyRelease(key), delay);
+    send_key(&EventType::KeyRelease(modifier), delay);
+}
+
+/// 解析形如 `Shift+Home` 的按键组合，返回修饰键列表与主键
+fn parse_key_combo(combo: &str) -> Option<(Vec<Key>, Key)> {
+    let mut parts = combo.split('+').map(str::trim).collect::<Vec<_>>();
+    let key = match parts.pop()? {
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "Left" => Key::LeftArrow,
+        "Right" => Key::RightArrow,
+        "Up" => Key::UpArrow,
+        "Down" => Key::DownArrow,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        _ => return None,
+    };
+    let modifiers = parts
+        .into_iter()
+        .map(|modifier| match modifier {
+            "Ctrl" => Some(Key::ControlLeft),
+            "Shift" => Some(Key::ShiftLeft),
+            "Alt" => Some(Key::Alt),
+            "Meta" => Some(Key::MetaLeft),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((modifiers, key))
+}
+
+fn simulate_keys(modifiers: &[Key], key: Key, delay: Duration) {
+    for modifier in modifiers {
+        send_key(&EventType::KeyPress(*modifier), delay);
+    }
+    send_key(&EventType::KeyPress(key), delay);
+    send_key(&EventType::KeyRelease(key), delay);
+    for modifier in modifiers.iter().rev() {
+        send_key(&EventType::KeyRelease(*modifier), delay);
+    }
+}
+
+/// 选中当前行：先移动到行首，再按住 Shift 移动到行尾
+fn select_current_line(config: &Config) -> bool {
+    let (Some((start_modifiers, start_key)), Some((mut end_modifiers, end_key))) = (
+        parse_key_combo(&config.line_start_key),
+        parse_key_combo(&config.line_end_key),
+    ) else {
+        eprintln!(
+            "无法解析行首/行尾按键 '{}' / '{}'",
+            config.line_start_key, config.line_end_key
+        );
+        return false;
+    };
+
+    let delay = Duration::from_millis(config.key_delay_ms);
+    simulate_keys(&start_modifiers, start_key, delay);
+    if !end_modifiers.contains(&Key::ShiftLeft) {
+        end_modifiers.push(Key::ShiftLeft);
+    }
+    simulate_keys(&end_modifiers, end_key, delay);
+    true
+}
+
+/// 按下行尾按键取消选中，使光标回到当前行末尾
+fn deselect_current_line(config: &Config) {
+    if let Some((modifiers, key)) = parse_key_combo(&config.line_end_key) {
+        simulate_keys(&modifiers, key, Duration::from_millis(config.key_delay_ms));
+    }
+}
+
+/// 记录当前行是否处于选中状态，离开作用域时仍选中则取消选中
+struct LineSelection<'a> {
+    config: &'a Config,
+    selected: bool,
+}
+
+impl LineSelection<'_> {
+    fn deselect(&mut self) {
+        if self.selected {
+            deselect_current_line(self.config);
+            self.selected = false;
+        }
+    }
+}
+
+impl Drop for LineSelection<'_> {
+    fn drop(&mut self) {
+        self.deselect();
+    }
+}
+
+enum ClipboardContent {
+    Text(String),
+    Image(ImageData<'static>),
+    /// 原剪贴板为空或内容无法读取，恢复时清空剪贴板
+    Empty,
+}
+
+/// 程序最后写入剪贴板的内容，用于判断恢复前剪贴板是否已被其他程序修改
+enum PlacedContent {
+    Text(String),
+    Image { width: usize, height: usize },
+}
+
+fn save_clipboard(clipboard: &mut Clipboard) -> ClipboardContent {
+    clipboard
+        .get_text()
+        .map(ClipboardContent::Text)
+        .or_else(|_| clipboard.get_image().map(ClipboardContent::Image))
+        .unwrap_or(ClipboardContent::Empty)
+}
+
+fn is_still_placed(clipboard: &mut Clipboard, placed: &PlacedContent) -> bool {
+    match placed {
+        PlacedContent::Text(text) => clipboard.get_text().is_ok_and(|t| &t == text),
+        PlacedContent::Image { width, height } => clipboard
+            .get_image()
+            .is_ok_and(|image| image.width == *width && image.height == *height),
+    }
+}
+
+fn restore_clipboard(clipboard: &mut Clipboard, saved: ClipboardContent) {
+    match saved {
+        ClipboardContent::Text(text) => clipboard.set_text(text).ok(),
+        ClipboardContent::Image(image) => clipboard.set_image(image).ok(),
+        ClipboardContent::Empty => clipboard.clear().ok(),
+    };
+}
+
+/// 持有剪贴板并在离开作用域时恢复保存的内容，保证处理中途退出时剪贴板不被复制的文字覆盖
+struct ClipboardGuard {
+    clipboard: Clipboard,
+    saved: Option<ClipboardContent>,
+    placed: Option<PlacedContent>,
+    /// 写入生成内容后是否仍恢复，未启用恢复或仅复制模式下保留生成的内容
+    restore_placed: bool,
+    delay: Duration,
+}
+
+impl ClipboardGuard {
+    fn new(mut clipboard: Clipboard, config: &Config, mode: ProcessMode) -> Self {
+        // 选中模式会在复制前清空剪贴板，无论是否启用恢复都需要先保存
+        let saved = (config.restore_clipboard || config.capture_mode == CaptureMode::Selection)
+            .then(|| save_clipboard(&mut clipboard));
+        ClipboardGuard {
+            clipboard,
+            saved,
+            placed: None,
+            restore_placed: config.restore_clipboard && mode != ProcessMode::Copy,
+            delay: Duration::from_millis(config.restore_clipboard_delay_ms),
+        }
+    }
+}
+
+impl Drop for ClipboardGuard {
+    fn drop(&mut self) {
+        let Some(saved) = self.saved.take() else {
+            return;
+        };
+
+        match &self.placed {
+            // 尚未写入生成的内容，剪贴板中只有复制的文字，立即恢复
+            None => restore_clipboard(&mut self.clipboard, saved),
+            // 等待目标程序读取粘贴内容后再恢复，剪贴板已被修改时不做处理
+            Some(placed) if self.restore_placed => {
+                thread::sleep(self.delay);
+                if is_still_placed(&mut self.clipboard, placed) {
+                    restore_clipboard(&mut self.clipboard, saved);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+fn sanitize_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter(|&c| {
+            !matches!(c, '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}')
+                && (!c.is_control() || c == '\t' || c == '\n')
+        })
+        .collect()
+}
+
+fn in_cooldown(
+    data_manager: &DataManager,
+    last_used: &HashMap<String, Instant>,
+    character_id: &str,
+) -> bool {
+    let cooldown_ms = data_manager
+        .get_character(character_id)
+        .and_then(|c| c.cooldown_ms);
+
+    match (cooldown_ms, last_used.get(character_id)) {
+        (Some(cooldown_ms), Some(last)) => last.elapsed() < Duration::from_millis(cooldown_ms),
+        _ => false,
+    }
+}
+
+fn select_character(
+    config: &Config,
+    data_manager: &DataManager,
+    last_used: &HashMap<String, Instant>,
+) -> Option<String> {
+    if !in_cooldown(data_manager, last_used, &config.current_character) {
+        return Some(config.current_character.clone());
+    }
+
+    config
+        .cooldown_fallback
+        .clone()
+        .filter(|id| !in_cooldown(data_manager, last_used, id))
+}
+
+/// 文字是否以句末标点结尾，用于判断消息是否已输入完整
+fn is_complete(text: &str, terminal_punctuation: &str) -> bool {
+    text.trim_end()
+        .chars()
+        .next_back()
+        .is_some_and(|c| terminal_punctuation.contains(c))
+}
+
+/// 获取引用的上文，剪贴板来源需在选中复制文字之前读取
+fn read_context(config: &Config, clipboard: &mut Clipboard) -> Option<String> {
+    let context = match config.context_source {
+        ContextSource::None => return None,
+        ContextSource::Clipboard => clipboard.get_text().ok()?,
+        ContextSource::Fixed => config.context_text.clone(),
+    };
+
+    let context = if config.sanitize_text {
+        sanitize_text(&context)
+    } else {
+        context
+    };
+    Some(context).filter(|context| !context.trim().is_empty())
+}
+
+fn format_caption(character: &CharacterConfig) -> Option<String> {
+    let caption = character.caption.as_ref()?;
+    Some(
+        caption
+            .replace("%c", &character.id)
+            .replace("%n", &character.name),
+    )
 }
 
 pub fn process_image(
     config: &Config,
     data_manager: &DataManager,
+    last_used: &Mutex<HashMap<String, Instant>>,
+    stats: &Mutex<Stats>,
     mode: ProcessMode,
     enable_max_chars: bool,
 ) {
-    let Ok(mut clipboard) = Clipboard::new() else {
+    let Some(character_id) = select_character(config, data_manager, &last_used.lock().unwrap())
+    else {
+        eprintln!("角色 '{}' 冷却中，已跳过生成", config.current_character);
+        let character_name = data_manager
+            .get_character(&config.current_character)
+            .map_or(config.current_character.as_str(), |c| c.name.as_str());
+        notify_cooldown(character_name);
         return;
     };
 
-    simulate_key_combo(Key::KeyA);
-    thread::sleep(Duration::from_millis(20));
+    let Ok(clipboard) = Clipboard::new() else {
+        return;
+    };
+    let mut clipboard = ClipboardGuard::new(clipboard, config, mode);
 
-    simulate_key_combo(Key::KeyC);
-    thread::sleep(Duration::from_millis(30));
+    let context = read_context(config, &mut clipboard.clipboard);
 
-    let Ok(copied_content) = clipboard.get_text() else {
+    let key_delay = Duration::from_millis(config.key_delay_ms);
+    let mut timing = Timing::default();
+    let mut checkpoint = Instant::now();
+
+    let mut line_selection = LineSelection {
+        config,
+        selected: false,
+    };
+    match config.capture_mode {
+        CaptureMode::All => simulate_key_combo(Key::KeyA, key_delay),
+        CaptureMode::CurrentLine => {
+            if !select_current_line(config) {
+                return;
+            }
+            line_selection.selected = true;
+        }
+        // 没有选中文字时复制不会改变剪贴板，先清空以免将剪贴板中原有的文字当作输入，
+        // 原内容已保存，未生成图片时会恢复
+        CaptureMode::Selection => {
+            clipboard.clipboard.clear().ok();
+        }
+    }
+    if config.capture_mode != CaptureMode::Selection {
+        thread::sleep(Duration::from_millis(config.select_delay_ms));
+    }
+
+    simulate_key_combo(Key::KeyC, key_delay);
+    thread::sleep(Duration::from_millis(config.copy_delay_ms));
+
+    let Ok(copied_content) = clipboard.clipboard.get_text() else {
         return;
     };
 
+    // 仅复制模式下不会粘贴替换选中的行，需要手动取消选中
+    if mode == ProcessMode::Copy {
+        line_selection.deselect();
+    }
+
+    timing.capture = checkpoint.elapsed();
+
+    let copied_content = if config.sanitize_text {
+        sanitize_text(&copied_content)
+    } else {
+        copied_content
+    };
+
     if copied_content.is_empty() {
         return;
     }
 
+    // 消息未输入完整时不发送，以便继续输入
+    let should_send = mode == ProcessMode::Send
+        && (!config.auto_send_on_complete
+            || is_complete(&copied_content, &config.terminal_punctuation));
+
     if mode == ProcessMode::Send
         && enable_max_chars
         && config.max_chars > 0
         && copied_content.chars().count() > config.max_chars
     {
-        send_key(&EventType::KeyPress(Key::Return));
-        send_key(&EventType::KeyRelease(Key::Return));
+        // 保留原文字发送，先取消选中以免 Enter 替换选中的行
+        line_selection.deselect();
+        if should_send {
+            send_key(&EventType::KeyPress(Key::Return), key_delay);
+            send_key(&EventType::KeyRelease(Key::Return), key_delay);
+        }
         return;
     }
 
+    checkpoint = Instant::now();
+    // 上文与本次文字相同时不重复引用
     let image = {
         match generate_image(
             data_manager,
-            &config.current_character,
+            &character_id,
             &copied_content,
-            config.max_image_size,
+            context
+                .as_deref()
+                .filter(|context| *context != copied_content),
+            0,
             None,
         ) {
             Ok(img) => img,
@@ -75,24 +393,95 @@ pub fn process_image(
         }
     };
 
+    timing.generate = checkpoint.elapsed();
+
+    // 压缩单独计时，便于区分绘制与编码的耗时
+    checkpoint = Instant::now();
+    let image = if config.max_image_size > 0 {
+        match compress_character_image(
+            data_manager,
+            &character_id,
+            image,
+            config.max_image_size,
+            &EncodeOptions::default(),
+        ) {
+            Ok((img, _)) => img,
+            Err(_) => {
+                return;
+            }
+        }
+    } else {
+        image
+    };
+    timing.encode = checkpoint.elapsed();
+
+    last_used
+        .lock()
+        .unwrap()
+        .insert(character_id.clone(), Instant::now());
+
+    checkpoint = Instant::now();
     let (width, height) = image.dimensions();
     let image_data = ImageData {
         width: width as usize,
         height: height as usize,
         bytes: image.into_raw().into(),
     };
+    let placed = PlacedContent::Image {
+        width: image_data.width,
+        height: image_data.height,
+    };
 
-    if clipboard.set_image(image_data).is_err() {
+    if clipboard.clipboard.set_image(image_data).is_err() {
         return;
     }
+    clipboard.placed = Some(placed);
+    timing.clipboard = checkpoint.elapsed();
 
+    checkpoint = Instant::now();
     if mode != ProcessMode::Copy {
-        simulate_key_combo(Key::KeyV);
-        thread::sleep(Duration::from_millis(100));
+        // 粘贴会替换选中的行，之后无需取消选中
+        line_selection.selected = false;
+        simulate_key_combo(Key::KeyV, key_delay);
+        thread::sleep(Duration::from_millis(config.paste_delay_ms));
 
-        if mode == ProcessMode::Send {
-            send_key(&EventType::KeyPress(Key::Return));
-            send_key(&EventType::KeyRelease(Key::Return));
+        if let Some(caption) = data_manager
+            .get_character(&character_id)
+            .and_then(format_caption)
+            && clipboard.clipboard.set_text(caption.clone()).is_ok()
+        {
+            clipboard.placed = Some(PlacedContent::Text(caption));
+            simulate_key_combo(Key::KeyV, key_delay);
+            thread::sleep(Duration::from_millis(config.paste_delay_ms));
         }
+
+        if should_send {
+            send_key(&EventType::KeyPress(Key::Return), key_delay);
+            send_key(&EventType::KeyRelease(Key::Return), key_delay);
+        }
+    }
+    timing.paste = checkpoint.elapsed();
+
+    stats.lock().unwrap().record(timing);
+
+    if let Some(character) = data_manager.get_character(&character_id) {
+        notify_generated(config, &character.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_text_normalizes_line_endings_and_strips_invisible_chars() {
+        let text = "第一行\r\n第\u{200B}二\u{FEFF}行\r第三行\u{7}\t结束\u{200D}";
+        assert_eq!(sanitize_text(text), "第一行\n第二行\n第三行\t结束");
+    }
+
+    #[test]
+    fn sanitize_text_keeps_plain_text() {
+        let text = "Hello, 世界！\n\tindent";
+        assert_eq!(sanitize_text(text), text);
     }
 }
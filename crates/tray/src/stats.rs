@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+const MAX_RECORDS: usize = 20;
+
+#[derive(Clone, Copy, Default)]
+pub struct Timing {
+    pub capture: Duration,
+    pub generate: Duration,
+    pub encode: Duration,
+    pub clipboard: Duration,
+    pub paste: Duration,
+}
+
+impl Timing {
+    fn total(&self) -> Duration {
+        self.capture + self.generate + self.encode + self.clipboard + self.paste
+    }
+}
+
+#[derive(Default)]
+pub struct Stats {
+    records: VecDeque<Timing>,
+}
+
+impl Stats {
+    pub fn record(&mut self, timing: Timing) {
+        if self.records.len() >= MAX_RECORDS {
+            self.records.pop_front();
+        }
+        self.records.push_back(timing);
+    }
+
+    pub fn summary(&self) -> String {
+        if self.records.is_empty() {
+            return "暂无生成记录".to_string();
+        }
+
+        let count = self.records.len();
+        let average = |f: fn(&Timing) -> Duration| {
+            self.records.iter().map(f).sum::<Duration>().as_millis() / count as u128
+        };
+        let max_total = self
+            .records
+            .iter()
+            .map(Timing::total)
+            .max()
+            .unwrap_or_default()
+            .as_millis();
+
+        format!(
+            "最近 {} 次生成的平均耗时：\n\n复制文字：{} ms\n生成图片：{} ms\n压缩图片：{} ms\n写入剪贴板：{} ms\n粘贴发送：{} ms\n\n合计：{} ms(最长 {} ms)",
+            count,
+            average(|t| t.capture),
+            average(|t| t.generate),
+            average(|t| t.encode),
+            average(|t| t.clipboard),
+            average(|t| t.paste),
+            average(Timing::total),
+            max_total,
+        )
+    }
+}
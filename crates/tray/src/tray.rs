@@ -1,19 +1,26 @@
 use std::collections::HashMap;
+use std::path::Path;
 
-use anyhow::Result;
-use tray_icon::menu::{CheckMenuItem, Menu, MenuId, MenuItem, PredefinedMenuItem};
+use anyhow::{Result, bail};
+use tray_icon::menu::{CheckMenuItem, Menu, MenuId, MenuItem, PredefinedMenuItem, Submenu};
 use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
 
 use crate::config::{Config, ProcessMode};
 
 const ICON_DATA: &[u8] = include_bytes!("../assets/tray.raw");
+const ICON_SIZE: u32 = 32;
+const CUSTOM_ICON_SIZE_RANGE: std::ops::RangeInclusive<u32> = 16..=256;
 
 pub enum ControlMessage {
     SwitchCharacter(String),
+    SwitchHotkeyProfile(Option<String>),
+    SwitchProfile(String),
     ToggleAutoPaste,
     ToggleAutoSend,
     ToggleIntercept,
     ToggleWhitelist,
+    ResetConfig,
+    ShowStats,
     Help,
     Quit,
 }
@@ -28,6 +35,14 @@ pub struct TrayMenu {
     intercept_item: CheckMenuItem,
     whitelist_item: CheckMenuItem,
 
+    hotkey_profile_menu: Submenu,
+    hotkey_profile_items: Vec<(Option<String>, CheckMenuItem)>,
+
+    profile_menu: Submenu,
+    profile_items: Vec<(String, CheckMenuItem)>,
+
+    reset_item: MenuItem,
+    stats_item: MenuItem,
     help_item: MenuItem,
     quit_item: MenuItem,
 
@@ -53,6 +68,11 @@ impl TrayMenu {
         self.tray_icon.set_icon(Some(icon)).ok();
     }
 
+    pub fn disable_intercept(&self) {
+        self.intercept_item.set_enabled(false);
+        self.set_intercept_enter(false);
+    }
+
     pub fn set_whitelist_enabled(&self, enabled: bool) {
         self.whitelist_item.set_checked(enabled);
     }
@@ -68,6 +88,45 @@ impl TrayMenu {
         }
     }
 
+    /// 按配置重建快捷键方案子菜单，“默认”对应顶层的快捷键配置
+    pub fn set_hotkey_profiles(&mut self, config: &Config) {
+        for (_, item) in self.hotkey_profile_items.drain(..) {
+            self.hotkey_profile_menu.remove(&item).ok();
+        }
+
+        let active = config
+            .active_hotkey_profile
+            .as_ref()
+            .filter(|name| config.hotkey_profiles.contains_key(*name));
+
+        let profiles = std::iter::once(None).chain(config.hotkey_profiles.keys().map(Some));
+        for profile in profiles {
+            let text = profile.map_or("默认", String::as_str);
+            let item = CheckMenuItem::new(text, true, profile == active, None);
+            self.hotkey_profile_menu.append(&item).ok();
+            self.hotkey_profile_items.push((profile.cloned(), item));
+        }
+
+        self.hotkey_profile_menu
+            .set_enabled(!config.hotkey_profiles.is_empty());
+    }
+
+    /// 按配置重建配置方案子菜单
+    pub fn set_profiles(&mut self, config: &Config) {
+        for (_, item) in self.profile_items.drain(..) {
+            self.profile_menu.remove(&item).ok();
+        }
+
+        for name in config.profiles.keys() {
+            let is_active = config.active_profile.as_ref() == Some(name);
+            let item = CheckMenuItem::new(name, true, is_active, None);
+            self.profile_menu.append(&item).ok();
+            self.profile_items.push((name.clone(), item));
+        }
+
+        self.profile_menu.set_enabled(!config.profiles.is_empty());
+    }
+
     pub fn event_to_message(&self, event_id: &MenuId) -> Option<ControlMessage> {
         if event_id == self.auto_paste_item.id() {
             Some(ControlMessage::ToggleAutoPaste)
@@ -77,10 +136,26 @@ impl TrayMenu {
             Some(ControlMessage::ToggleIntercept)
         } else if event_id == self.whitelist_item.id() {
             Some(ControlMessage::ToggleWhitelist)
+        } else if event_id == self.reset_item.id() {
+            Some(ControlMessage::ResetConfig)
+        } else if event_id == self.stats_item.id() {
+            Some(ControlMessage::ShowStats)
         } else if event_id == self.help_item.id() {
             Some(ControlMessage::Help)
         } else if event_id == self.quit_item.id() {
             Some(ControlMessage::Quit)
+        } else if let Some((profile, _)) = self
+            .hotkey_profile_items
+            .iter()
+            .find(|(_, item)| item.id() == event_id)
+        {
+            Some(ControlMessage::SwitchHotkeyProfile(profile.clone()))
+        } else if let Some((name, _)) = self
+            .profile_items
+            .iter()
+            .find(|(_, item)| item.id() == event_id)
+        {
+            Some(ControlMessage::SwitchProfile(name.clone()))
         } else {
             self.character_id_map
                 .get(event_id)
@@ -89,7 +164,11 @@ impl TrayMenu {
     }
 }
 
-pub fn create_tray_menu(characters: &HashMap<String, String>, config: &Config) -> Result<TrayMenu> {
+pub fn create_tray_menu(
+    characters: &HashMap<String, String>,
+    config: &Config,
+    custom_icon_path: &Path,
+) -> Result<TrayMenu> {
     let menu = Menu::new();
 
     let mut character_items = HashMap::new();
@@ -135,16 +214,29 @@ pub fn create_tray_menu(characters: &HashMap<String, String>, config: &Config) -
     let whitelist_item = CheckMenuItem::new("使用白名单", true, config.enable_whitelist, None);
     menu.append(&whitelist_item)?;
 
+    let hotkey_profile_menu = Submenu::new("快捷键方案", true);
+    menu.append(&hotkey_profile_menu)?;
+
+    let profile_menu = Submenu::new("配置方案", true);
+    menu.append(&profile_menu)?;
+
     menu.append(&PredefinedMenuItem::separator())?;
 
+    let reset_item = MenuItem::new("重置设置", true, None);
+    menu.append(&reset_item)?;
+
+    let stats_item = MenuItem::new("性能统计", true, None);
+    menu.append(&stats_item)?;
+
     let help_item = MenuItem::new("帮助", true, None);
     menu.append(&help_item)?;
 
     let quit_item = MenuItem::new("退出", true, None);
     menu.append(&quit_item)?;
 
-    let color_icon = create_icon(false)?;
-    let gray_icon = create_icon(true)?;
+    let icon_image = load_icon_image(custom_icon_path);
+    let color_icon = create_icon(&icon_image, false)?;
+    let gray_icon = create_icon(&icon_image, true)?;
 
     let icon = if config.intercept_enter {
         color_icon.clone()
@@ -163,25 +255,76 @@ pub fn create_tray_menu(characters: &HashMap<String, String>, config: &Config) -
         .with_icon(icon)
         .build()?;
 
-    let tray_menu = TrayMenu {
+    let mut tray_menu = TrayMenu {
         character_items,
         character_id_map,
         auto_paste_item,
         auto_send_item,
         intercept_item,
         whitelist_item,
+        hotkey_profile_menu,
+        hotkey_profile_items: Vec::new(),
+        profile_menu,
+        profile_items: Vec::new(),
+        reset_item,
+        stats_item,
         help_item,
         quit_item,
         tray_icon,
         color_icon,
         gray_icon,
     };
+    tray_menu.set_hotkey_profiles(config);
+    tray_menu.set_profiles(config);
 
     Ok(tray_menu)
 }
 
-fn create_icon(grayscale: bool) -> Result<Icon> {
-    let mut rgba = ICON_DATA.to_vec();
+struct IconImage {
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+/// 自定义图标存在且有效时使用自定义图标，否则使用内置图标
+fn load_icon_image(custom_icon_path: &Path) -> IconImage {
+    if custom_icon_path.exists() {
+        match load_custom_icon(custom_icon_path) {
+            Ok(icon_image) => return icon_image,
+            Err(e) => eprintln!("自定义托盘图标加载失败，使用内置图标：{}", e),
+        }
+    }
+
+    IconImage {
+        rgba: ICON_DATA.to_vec(),
+        width: ICON_SIZE,
+        height: ICON_SIZE,
+    }
+}
+
+fn load_custom_icon(path: &Path) -> Result<IconImage> {
+    let image = image::open(path)?.into_rgba8();
+    let (width, height) = image.dimensions();
+
+    if width != height || !CUSTOM_ICON_SIZE_RANGE.contains(&width) {
+        bail!(
+            "托盘图标应为边长 {} 至 {} 像素的正方形图片，实际尺寸为 {}x{}",
+            CUSTOM_ICON_SIZE_RANGE.start(),
+            CUSTOM_ICON_SIZE_RANGE.end(),
+            width,
+            height
+        );
+    }
+
+    Ok(IconImage {
+        rgba: image.into_raw(),
+        width,
+        height,
+    })
+}
+
+fn create_icon(icon_image: &IconImage, grayscale: bool) -> Result<Icon> {
+    let mut rgba = icon_image.rgba.clone();
 
     if grayscale {
         for chunk in rgba.chunks_exact_mut(4) {
@@ -195,5 +338,5 @@ fn create_icon(grayscale: bool) -> Result<Icon> {
         }
     }
 
-    Icon::from_rgba(rgba, 32, 32).map_err(Into::into)
+    Icon::from_rgba(rgba, icon_image.width, icon_image.height).map_err(Into::into)
 }